@@ -0,0 +1,151 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+
+// Optional columns of the balances table, the name is always shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Component {
+    AssetClass,
+    Amount,
+    Balance,
+    Weight,
+    Dividend,
+}
+
+impl Component {
+    pub const ALL: [Component; 5] = [
+        Component::AssetClass,
+        Component::Amount,
+        Component::Balance,
+        Component::Weight,
+        Component::Dividend,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Component::AssetClass => "asset_class",
+            Component::Amount => "amount",
+            Component::Balance => "balance",
+            Component::Weight => "weight",
+            Component::Dividend => "dividend",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Component::AssetClass => "Asset class column",
+            Component::Amount => "Amount column",
+            Component::Balance => "Balance column",
+            Component::Weight => "Weight, target weight and drift columns",
+            Component::Dividend => "Projected annual dividend income column",
+        }
+    }
+}
+
+impl FromStr for Component {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Component::ALL
+            .into_iter()
+            .find(|c| c.as_str() == s.trim().to_lowercase())
+            .ok_or_else(|| {
+                let names: Vec<&str> = Component::ALL.iter().map(|c| c.as_str()).collect();
+                format!(
+                    "Unknown component \"{}\", valid components are: {}",
+                    s.trim(),
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct DisabledComponents(HashSet<Component>);
+
+impl DisabledComponents {
+    // Parse a comma separated list like "amount,weight"
+    pub fn from_list(list: &str) -> Result<Self, String> {
+        list.split(',')
+            .filter(|s| !s.trim().is_empty())
+            .map(Component::from_str)
+            .collect::<Result<HashSet<_>, _>>()
+            .map(DisabledComponents)
+    }
+
+    // Parse a comma separated list of the only components to show,
+    // all others are disabled
+    pub fn from_fields(list: &str) -> Result<Self, String> {
+        let shown = DisabledComponents::from_list(list)?;
+        Ok(DisabledComponents(
+            Component::ALL
+                .into_iter()
+                .filter(|c| !shown.is_disabled(*c))
+                .collect(),
+        ))
+    }
+
+    pub fn is_disabled(&self, component: Component) -> bool {
+        self.0.contains(&component)
+    }
+
+    pub fn all_disabled(&self) -> bool {
+        Component::ALL.iter().all(|c| self.is_disabled(*c))
+    }
+}
+
+// Help text listing all components for --disable
+pub fn components_help() -> String {
+    Component::ALL
+        .iter()
+        .map(|c| format!("{}: {}", c.as_str(), c.description()))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_component_from_str() {
+        assert_eq!(Component::from_str("amount"), Ok(Component::Amount));
+        assert_eq!(
+            Component::from_str(" Asset_Class "),
+            Ok(Component::AssetClass)
+        );
+        assert!(Component::from_str("price").is_err());
+
+        for component in Component::ALL {
+            assert_eq!(Component::from_str(component.as_str()), Ok(component));
+        }
+    }
+
+    #[test]
+    fn test_disabled_components() {
+        let disabled = DisabledComponents::from_list("amount, weight").unwrap();
+        assert!(disabled.is_disabled(Component::Amount));
+        assert!(disabled.is_disabled(Component::Weight));
+        assert!(!disabled.is_disabled(Component::Balance));
+        assert!(!disabled.all_disabled());
+
+        let disabled =
+            DisabledComponents::from_list("asset_class,amount,balance,weight,dividend").unwrap();
+        assert!(disabled.all_disabled());
+
+        assert!(DisabledComponents::from_list("amount,foo").is_err());
+        assert!(!DisabledComponents::from_list("").unwrap().all_disabled());
+    }
+
+    #[test]
+    fn test_disabled_components_from_fields() {
+        let disabled = DisabledComponents::from_fields("balance,weight").unwrap();
+        assert!(!disabled.is_disabled(Component::Balance));
+        assert!(!disabled.is_disabled(Component::Weight));
+        assert!(disabled.is_disabled(Component::AssetClass));
+        assert!(disabled.is_disabled(Component::Amount));
+        assert!(disabled.is_disabled(Component::Dividend));
+
+        assert!(DisabledComponents::from_fields("").unwrap().all_disabled());
+        assert!(DisabledComponents::from_fields("balance,price").is_err());
+    }
+}
@@ -0,0 +1,367 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+// How a modified portfolio file is written back
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JsonFormat {
+    // keep the style (compact or pretty, indentation) of the original file
+    #[default]
+    Auto,
+    Pretty,
+    Compact,
+}
+
+// Compact JSON with a space after every ":" and ",", e.g. {"Amount": 1, "Ticker": "A"}
+struct SpacedFormatter;
+
+impl serde_json::ser::Formatter for SpacedFormatter {
+    fn begin_array_value<W: ?Sized + std::io::Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> std::io::Result<()> {
+        if first {
+            Ok(())
+        } else {
+            writer.write_all(b", ")
+        }
+    }
+
+    fn begin_object_key<W: ?Sized + std::io::Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> std::io::Result<()> {
+        self.begin_array_value(writer, first)
+    }
+
+    fn begin_object_value<W: ?Sized + std::io::Write>(
+        &mut self,
+        writer: &mut W,
+    ) -> std::io::Result<()> {
+        writer.write_all(b": ")
+    }
+}
+
+fn serialize_with(value: &Value, formatter: impl serde_json::ser::Formatter) -> String {
+    let mut buf = Vec::new();
+    let mut serializer = serde_json::Serializer::with_formatter(&mut buf, formatter);
+    value.serialize(&mut serializer).unwrap();
+    String::from_utf8(buf).unwrap()
+}
+
+fn leading_whitespace(line: &str) -> &str {
+    &line[..line.len() - line.trim_start().len()]
+}
+
+// Layout of a JSON file as found on disk
+#[derive(Debug, PartialEq)]
+enum Layout {
+    // everything on one line
+    Compact {
+        spaced: bool,
+    },
+    // one array element per line, e.g. `  {"Ticker": "A", "Amount": 1},`
+    LinePerElement {
+        spaced: bool,
+        indent: String,
+        // `[` and `]` on lines of their own
+        open_alone: bool,
+        close_indent: Option<String>,
+    },
+    // every key on its own line, indented by `indent` per level (may be empty)
+    Pretty {
+        indent: String,
+    },
+}
+
+fn detect_layout(original: &str) -> Layout {
+    let trimmed = original.trim();
+    let spaced = trimmed.contains("\": ");
+    let lines: Vec<&str> = trimmed.lines().collect();
+    if lines.len() < 2 {
+        return Layout::Compact { spaced };
+    }
+
+    // pretty files have object keys at the start of a line
+    if lines.iter().any(|line| line.trim_start().starts_with('"')) {
+        let indent = lines
+            .iter()
+            .map(|line| leading_whitespace(line))
+            .filter(|indent| !indent.is_empty())
+            .min_by_key(|indent| indent.len())
+            .unwrap_or_default();
+        return Layout::Pretty {
+            indent: indent.to_string(),
+        };
+    }
+
+    let last = lines[lines.len() - 1];
+    Layout::LinePerElement {
+        spaced,
+        indent: lines[1..]
+            .iter()
+            .find(|line| line.trim() != "]")
+            .map_or("", |line| leading_whitespace(line))
+            .to_string(),
+        open_alone: lines[0] == "[",
+        close_indent: (last.trim() == "]").then(|| leading_whitespace(last).to_string()),
+    }
+}
+
+fn compact(value: &Value, spaced: bool) -> String {
+    if spaced {
+        serialize_with(value, SpacedFormatter)
+    } else {
+        value.to_string()
+    }
+}
+
+// Serialize `value` in the requested format, `original` is the file content
+// the style is taken from in auto mode. The trailing newline of `original`
+// is kept in all formats.
+pub fn to_json_string(value: &Value, original: &str, format: JsonFormat) -> String {
+    let layout = match format {
+        JsonFormat::Compact => Layout::Compact { spaced: false },
+        JsonFormat::Pretty => Layout::Pretty {
+            indent: "  ".to_string(),
+        },
+        JsonFormat::Auto => detect_layout(original),
+    };
+
+    let json = match (layout, value.as_array()) {
+        (Layout::Compact { spaced }, _) => compact(value, spaced),
+        (Layout::Pretty { indent }, _) => serialize_with(
+            value,
+            serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes()),
+        ),
+        (
+            Layout::LinePerElement {
+                spaced,
+                indent,
+                open_alone,
+                close_indent,
+            },
+            Some(elements),
+        ) => {
+            let mut json = String::from("[");
+            if open_alone {
+                json.push('\n');
+                json.push_str(&indent);
+            }
+            let elements: Vec<String> = elements.iter().map(|e| compact(e, spaced)).collect();
+            json.push_str(&elements.join(&format!(",\n{}", indent)));
+            if let Some(close_indent) = close_indent {
+                json.push('\n');
+                json.push_str(&close_indent);
+            }
+            json.push(']');
+            json
+        }
+        (Layout::LinePerElement { spaced, .. }, None) => compact(value, spaced),
+    };
+    json + &original[original.trim_end().len()..]
+}
+
+// A corporate action turning every share of `from` into `ratio` shares of `to`
+// plus `cash_per_share` in cash, e.g. a merger, a spinoff or a stock split
+pub struct CorporateAction {
+    pub from: String,
+    pub to: String,
+    pub ratio: f64,
+    pub cash_per_share: f64,
+    // keep the `from` position, as in a spinoff
+    pub keep: bool,
+}
+
+fn has_ticker(position: &Value, ticker: &str) -> bool {
+    position["Ticker"]
+        .as_str()
+        .is_some_and(|t| t.eq_ignore_ascii_case(ticker))
+}
+
+fn add_amount(position: &mut Value, amount: f64) {
+    let current = position["Amount"].as_f64().unwrap_or(0.0);
+    position["Amount"] = json!(current + amount);
+}
+
+// Applies the action to the JSON positions and returns the updated JSON.
+// Fields the tool does not know about are left untouched.
+pub fn apply_corporate_action(
+    data: &str,
+    action: &CorporateAction,
+    format: JsonFormat,
+) -> Result<String, String> {
+    let mut positions: Vec<Value> =
+        serde_json::from_str(data).map_err(|e| format!("JSON was not well-formatted: {}", e))?;
+
+    let index = positions
+        .iter()
+        .position(|p| has_ticker(p, &action.from))
+        .ok_or(format!("No position with ticker {} found", action.from))?;
+
+    let amount = positions[index]["Amount"]
+        .as_f64()
+        .ok_or(format!("Position {} has no Amount", action.from))?;
+    let asset_class = positions[index]["AssetClass"].clone();
+    let new_shares = amount * action.ratio;
+
+    if action.from.eq_ignore_ascii_case(&action.to) {
+        positions[index]["Amount"] = json!(new_shares);
+    } else {
+        if !action.keep {
+            positions.remove(index);
+        }
+        match positions.iter_mut().find(|p| has_ticker(p, &action.to)) {
+            Some(position) => add_amount(position, new_shares),
+            None => positions.push(json!({
+                "Ticker": action.to,
+                "AssetClass": asset_class,
+                "Amount": new_shares,
+            })),
+        }
+    }
+
+    let cash = amount * action.cash_per_share;
+    if cash != 0.0 {
+        let cash_position = positions.iter_mut().find(|p| {
+            p["Ticker"].is_null()
+                && p["AssetClass"]
+                    .as_str()
+                    .is_some_and(|c| c.eq_ignore_ascii_case("cash"))
+        });
+        match cash_position {
+            Some(position) => add_amount(position, cash),
+            None => positions.push(json!({
+                "Name": "Cash",
+                "AssetClass": "Cash",
+                "Amount": cash,
+            })),
+        }
+    }
+
+    Ok(to_json_string(&Value::from(positions), data, format))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: &str = r#"[
+        {"Name": "Old Corp", "Ticker": "OLD", "AssetClass": "Stocks", "Amount": 10},
+        {"Ticker": "NEW", "AssetClass": "Stocks", "Amount": 1, "Note": "keep me"},
+        {"Name": "Cash", "AssetClass": "Cash", "Amount": 100}
+    ]"#;
+
+    fn action(from: &str, to: &str, ratio: f64, cash_per_share: f64) -> CorporateAction {
+        CorporateAction {
+            from: from.to_string(),
+            to: to.to_string(),
+            ratio,
+            cash_per_share,
+            keep: false,
+        }
+    }
+
+    #[test]
+    fn test_merger_with_cash() {
+        let result =
+            apply_corporate_action(DATA, &action("OLD", "NEW", 0.5, 2.0), JsonFormat::Auto)
+                .unwrap();
+        let positions: Vec<Value> = serde_json::from_str(&result).unwrap();
+
+        assert_eq!(positions.len(), 2);
+        assert_eq!(positions[0]["Ticker"], "NEW");
+        assert_eq!(positions[0]["Amount"], 6.0);
+        assert_eq!(positions[0]["Note"], "keep me");
+        assert_eq!(positions[1]["Amount"], 120.0);
+    }
+
+    #[test]
+    fn test_spinoff_into_new_position() {
+        let mut spinoff = action("OLD", "SPIN", 0.2, 0.0);
+        spinoff.keep = true;
+        let result = apply_corporate_action(DATA, &spinoff, JsonFormat::Auto).unwrap();
+        let positions: Vec<Value> = serde_json::from_str(&result).unwrap();
+
+        assert_eq!(positions.len(), 4);
+        assert_eq!(positions[0]["Amount"], 10);
+        assert_eq!(positions[3]["Ticker"], "SPIN");
+        assert_eq!(positions[3]["AssetClass"], "Stocks");
+        assert_eq!(positions[3]["Amount"], 2.0);
+    }
+
+    #[test]
+    fn test_split() {
+        let result =
+            apply_corporate_action(DATA, &action("OLD", "OLD", 4.0, 0.0), JsonFormat::Auto)
+                .unwrap();
+        let positions: Vec<Value> = serde_json::from_str(&result).unwrap();
+
+        assert_eq!(positions.len(), 3);
+        assert_eq!(positions[0]["Amount"], 40.0);
+    }
+
+    #[test]
+    fn test_to_json_string_detects_layout() {
+        let value: Value =
+            serde_json::from_str(r#"[{"Ticker":"A","Amount":1},{"Amount":2}]"#).unwrap();
+
+        // one line, with and without spaces
+        let spaced = "[{\"Ticker\": \"A\", \"Amount\": 1}, {\"Amount\": 2}]\n";
+        assert_eq!(to_json_string(&value, spaced, JsonFormat::Auto), spaced);
+
+        // one object per line is not expanded
+        let lines = "[\n  {\"Ticker\": \"A\", \"Amount\": 1},\n  {\"Amount\": 2}\n]\n";
+        assert_eq!(to_json_string(&value, lines, JsonFormat::Auto), lines);
+        let lines = "[{\"Ticker\":\"A\",\"Amount\":1},\n {\"Amount\":2}]";
+        assert_eq!(to_json_string(&value, lines, JsonFormat::Auto), lines);
+
+        // pretty without indentation is not collapsed
+        let flat = "[\n{\n\"Ticker\": \"A\",\n\"Amount\": 1\n},\n{\n\"Amount\": 2\n}\n]";
+        assert_eq!(to_json_string(&value, flat, JsonFormat::Auto), flat);
+
+        // tabs
+        let tabs = "[\n\t{\n\t\t\"Ticker\": \"A\",\n\t\t\"Amount\": 1\n\t},\n\t{\n\t\t\"Amount\": 2\n\t}\n]\n";
+        assert_eq!(to_json_string(&value, tabs, JsonFormat::Auto), tabs);
+    }
+
+    #[test]
+    fn test_to_json_string_keeps_trailing_newline() {
+        let value: Value = serde_json::from_str(r#"[{"Amount":1}]"#).unwrap();
+        assert_eq!(
+            to_json_string(&value, "[{\"Amount\":1}]\n", JsonFormat::Compact),
+            "[{\"Amount\":1}]\n"
+        );
+        assert_eq!(
+            to_json_string(&value, "[{\"Amount\":1}]", JsonFormat::Compact),
+            "[{\"Amount\":1}]"
+        );
+        assert!(to_json_string(&value, "[]\r\n", JsonFormat::Pretty).ends_with("}\n]\r\n"));
+    }
+
+    #[test]
+    fn test_to_json_string_keeps_style() {
+        let compact = r#"[{"Ticker":"OLD","Amount":1}]"#;
+        let value: Value = serde_json::from_str(compact).unwrap();
+        assert_eq!(to_json_string(&value, compact, JsonFormat::Auto), compact);
+
+        let pretty = "[\n    {\n        \"Ticker\": \"OLD\",\n        \"Amount\": 1\n    }\n]";
+        let value: Value = serde_json::from_str(pretty).unwrap();
+        assert_eq!(to_json_string(&value, pretty, JsonFormat::Auto), pretty);
+
+        assert_eq!(
+            to_json_string(&value, pretty, JsonFormat::Pretty),
+            serde_json::to_string_pretty(&value).unwrap()
+        );
+        assert_eq!(to_json_string(&value, pretty, JsonFormat::Compact), compact);
+    }
+
+    #[test]
+    fn test_unknown_ticker() {
+        let result =
+            apply_corporate_action(DATA, &action("FOO", "NEW", 1.0, 0.0), JsonFormat::Auto);
+        assert_eq!(result, Err("No position with ticker FOO found".to_string()));
+    }
+}
@@ -0,0 +1,360 @@
+use crate::format::{format_number, format_signed};
+use crate::output::info;
+use crate::portfolio::Holding;
+use crate::portfolio::Portfolio;
+use chrono::prelude::*;
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+
+// tree holding a PortfolioSnapshot next to each stored balance
+const SNAPSHOT_TREE: &str = "snapshots";
+// format of the keys in all trees, in local time
+const KEY_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+// The whole portfolio as recorded by the balances subcommand
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PortfolioSnapshot {
+    pub currency: String,
+    pub total: f64,
+    pub allocation: HashMap<String, f64>,
+    pub positions: Vec<Holding>,
+}
+
+// Directory for persistent state, the configured `data_dir` wins over
+// the platform default (e.g. ~/.local/share/portfolio on Linux)
+pub fn data_dir(custom: Option<&str>) -> Option<PathBuf> {
+    match custom {
+        Some(dir) => Some(PathBuf::from(dir)),
+        None => ProjectDirs::from("rs", "", "portfolio").map(|p| p.data_dir().to_path_buf()),
+    }
+}
+
+pub fn db_path(data_dir: &Path) -> PathBuf {
+    data_dir.join("database")
+}
+
+pub fn open_db(data_dir: &Path) -> Option<sled::Db> {
+    let path = db_path(data_dir);
+
+    // earlier versions kept the database in the working directory
+    if !path.exists() && Path::new("database").is_dir() {
+        info(format!(
+            "Found a database in the current directory, move it to {} to keep your history",
+            path.display()
+        ));
+    }
+
+    match sled::open(path) {
+        Ok(db) => Some(db),
+        Err(e) => {
+            info(format!(
+                "Could not open database, history is disabled: {}",
+                e
+            ));
+            None
+        }
+    }
+}
+
+// The total stays in the default tree for the performance subcommand
+pub fn store_balance_in_db(
+    db: &sled::Db,
+    portfolio: &Portfolio,
+    currency: &str,
+) -> Result<(), sled::Error> {
+    let curr_value = portfolio.get_total_value();
+    let curr_time = Local::now().format(KEY_FORMAT).to_string();
+
+    db.insert(&curr_time, curr_value.to_string().as_bytes())?;
+
+    let snapshot = PortfolioSnapshot {
+        currency: currency.to_string(),
+        total: curr_value,
+        allocation: portfolio.get_allocation(false),
+        positions: portfolio.get_holdings(currency).positions,
+    };
+    db.open_tree(SNAPSHOT_TREE)?
+        .insert(&curr_time, serde_json::to_vec(&snapshot).unwrap())?;
+
+    // block until all operations are stable on disk
+    db.flush()?;
+    Ok(())
+}
+
+// Parses a tree key written in local time with KEY_FORMAT
+fn parse_key(key: &[u8]) -> Option<DateTime<Utc>> {
+    let time = NaiveDateTime::parse_from_str(&String::from_utf8_lossy(key), KEY_FORMAT).ok()?;
+    Local
+        .from_local_datetime(&time)
+        .earliest()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+// All snapshots recorded at or after `since` ("%Y-%m-%d ...") in chronological
+// order, entries that cannot be read are skipped
+pub fn load_snapshots(
+    db: &sled::Db,
+    since: &str,
+) -> Result<Vec<(DateTime<Utc>, PortfolioSnapshot)>, sled::Error> {
+    let mut snapshots = Vec::new();
+
+    for entry in db.open_tree(SNAPSHOT_TREE)?.range(since..) {
+        let (key, snapshot) = entry?;
+        if let (Some(time), Ok(snapshot)) = (parse_key(&key), serde_json::from_slice(&snapshot)) {
+            snapshots.push((time, snapshot));
+        }
+    }
+    Ok(snapshots)
+}
+
+// All stored totals recorded at or after `since` in chronological order
+pub fn load_balance_history(db: &sled::Db, since: &str) -> Result<Vec<(String, f64)>, sled::Error> {
+    let mut history = Vec::new();
+
+    for entry in db.range(since..) {
+        let (time, total) = entry?;
+        if let Ok(total) = String::from_utf8_lossy(&total).parse() {
+            history.push((String::from_utf8_lossy(&time).to_string(), total));
+        }
+    }
+    Ok(history)
+}
+
+// Plots `values` from left to right, one column per value and the
+// highest row first, each row is labeled with its value
+pub fn ascii_chart(values: &[f64], height: usize) -> Vec<String> {
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let step = (max - min) / (height - 1) as f64;
+
+    let rows: Vec<usize> = values
+        .iter()
+        .map(|value| {
+            if step > 0.0 {
+                ((value - min) / step).round() as usize
+            } else {
+                0
+            }
+        })
+        .collect();
+
+    (0..height)
+        .rev()
+        .map(|row| {
+            let line: String = rows
+                .iter()
+                .map(|r| if *r == row { '*' } else { ' ' })
+                .collect();
+            format!(
+                "{: >12} | {}",
+                format_number(min + step * row as f64),
+                line.trim_end()
+            )
+        })
+        .collect()
+}
+
+// widest chart in columns, longer histories are thinned out
+const CHART_WIDTH: usize = 60;
+const CHART_HEIGHT: usize = 10;
+
+pub fn print_balance_history(db: &sled::Db, since: &str, limit: Option<usize>) {
+    let mut history = match load_balance_history(db, since) {
+        Ok(history) => history,
+        Err(e) => {
+            eprintln!("Error reading balance history: {}", e);
+            return;
+        }
+    };
+    if let Some(limit) = limit {
+        history.drain(..history.len().saturating_sub(limit));
+    }
+
+    let (first, last) = match (history.first(), history.last()) {
+        (Some(first), Some(last)) if history.len() > 1 => (first, last),
+        _ => {
+            println!("Not enough balance history yet, run `portfolio_rs balances` to record it.");
+            return;
+        }
+    };
+
+    let every = history.len().div_ceil(CHART_WIDTH);
+    let values: Vec<f64> = history
+        .iter()
+        .step_by(every)
+        .map(|(_, total)| *total)
+        .collect();
+
+    println!("Balance history from {} to {}", first.0, last.0);
+    println!("====================================================");
+    for line in ascii_chart(&values, CHART_HEIGHT) {
+        println!("{}", line);
+    }
+    println!(
+        "{} recorded balances, latest {}",
+        history.len(),
+        format_number(last.1)
+    );
+}
+
+// Change of every asset class between two allocations as (class, from %, to %),
+// classes missing on one side count as 0%
+pub fn allocation_drift(
+    from: &HashMap<String, f64>,
+    to: &HashMap<String, f64>,
+) -> Vec<(String, f64, f64)> {
+    let mut classes: Vec<&String> = from.keys().chain(to.keys()).collect();
+    classes.sort();
+    classes.dedup();
+
+    classes
+        .into_iter()
+        .map(|class| {
+            (
+                class.to_string(),
+                *from.get(class).unwrap_or(&0.0),
+                *to.get(class).unwrap_or(&0.0),
+            )
+        })
+        .collect()
+}
+
+pub fn print_allocation_drift(db: &sled::Db, since: &str) {
+    let history = match load_snapshots(db, since) {
+        Ok(history) => history,
+        Err(e) => {
+            eprintln!("Error reading allocation history: {}", e);
+            return;
+        }
+    };
+
+    let (first, last) = match (history.first(), history.last()) {
+        (Some(first), Some(last)) if history.len() > 1 => (first, last),
+        _ => {
+            println!("Not enough allocation history since {} yet.", since);
+            return;
+        }
+    };
+
+    println!(
+        "Allocation drift from {} to {}",
+        first.0.with_timezone(&Local).format(KEY_FORMAT),
+        last.0.with_timezone(&Local).format(KEY_FORMAT)
+    );
+    println!("====================================================");
+    for (class, from, to) in allocation_drift(&first.1.allocation, &last.1.allocation) {
+        println!(
+            "{0: >12} | {1: >10} -> {2: >10} | {3: >10}",
+            class,
+            format_number(from),
+            format_number(to),
+            format_signed(to - from)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::from_string;
+
+    #[test]
+    fn test_allocation_drift() {
+        let from = HashMap::from([("Stocks".to_string(), 55.0), ("Cash".to_string(), 45.0)]);
+        let to = HashMap::from([("Stocks".to_string(), 63.0), ("Gold".to_string(), 37.0)]);
+
+        assert_eq!(
+            allocation_drift(&from, &to),
+            vec![
+                ("Cash".to_string(), 45.0, 0.0),
+                ("Gold".to_string(), 0.0, 37.0),
+                ("Stocks".to_string(), 55.0, 63.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_load_snapshots() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let mut portfolio = Portfolio::new();
+        for position in from_string(
+            r#"[{"Name": "Savings", "AssetClass": "Cash", "Amount": 250}]"#,
+            "Stocks",
+        )
+        .unwrap()
+        {
+            portfolio.add_position(position);
+        }
+        store_balance_in_db(&db, &portfolio, "EUR").unwrap();
+        db.open_tree(SNAPSHOT_TREE)
+            .unwrap()
+            .insert("2024-01-01 10:00:00", "{}")
+            .unwrap();
+
+        let snapshots = load_snapshots(&db, "").unwrap();
+        assert_eq!(snapshots.len(), 1);
+        let snapshot = &snapshots[0].1;
+        assert_eq!(snapshot.currency, "EUR");
+        assert_eq!(snapshot.total, 250.0);
+        assert_eq!(snapshot.allocation.get("Cash"), Some(&100.0));
+        assert_eq!(snapshot.positions[0].name, "Savings");
+
+        // the total is still stored on its own for the performance subcommand
+        let (_, total) = db.iter().last().unwrap().unwrap();
+        assert_eq!(&*total, b"250");
+    }
+
+    #[test]
+    fn test_ascii_chart() {
+        assert_eq!(
+            ascii_chart(&[100.0, 150.0, 200.0, 150.0], 3),
+            vec![
+                "      200.00 |   *",
+                "      150.00 |  * *",
+                "      100.00 | *",
+            ]
+        );
+        // a flat history stays on the bottom row
+        assert_eq!(ascii_chart(&[5.0, 5.0], 2)[1], "        5.00 | **");
+    }
+
+    #[test]
+    fn test_load_balance_history() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        db.insert("2024-01-01 10:00:00", "100").unwrap();
+        db.insert("2024-03-01 10:00:00", "110.5").unwrap();
+        db.insert("2024-06-01 10:00:00", "broken").unwrap();
+
+        let history = load_balance_history(&db, "2024-02-01").unwrap();
+        assert_eq!(history, vec![("2024-03-01 10:00:00".to_string(), 110.5)]);
+    }
+
+    #[test]
+    fn test_load_snapshots_since() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = db.open_tree(SNAPSHOT_TREE).unwrap();
+        for (time, stocks) in [
+            ("2024-01-01 10:00:00", 50.0),
+            ("2024-03-01 10:00:00", 55.0),
+            ("2024-06-01 10:00:00", 63.0),
+        ] {
+            let snapshot = PortfolioSnapshot {
+                currency: "EUR".to_string(),
+                total: 100.0,
+                allocation: HashMap::from([("Stocks".to_string(), stocks)]),
+                positions: Vec::new(),
+            };
+            tree.insert(time, serde_json::to_vec(&snapshot).unwrap())
+                .unwrap();
+        }
+
+        let snapshots = load_snapshots(&db, "2024-02-01").unwrap();
+        assert_eq!(snapshots.len(), 2);
+        let first = Local.with_ymd_and_hms(2024, 3, 1, 10, 0, 0).unwrap();
+        assert_eq!(snapshots[0].0, first.with_timezone(&Utc));
+        assert_eq!(snapshots[1].1.allocation.get("Stocks"), Some(&63.0));
+    }
+}
@@ -0,0 +1,174 @@
+use crate::db::db_path;
+use crate::position::PortfolioPosition;
+use crate::position::PriceSource;
+use colored::Colorize;
+use std::path::Path;
+
+// ticker used to check that Yahoo Finance is reachable
+const TEST_TICKER: &str = "AAPL";
+
+// Outcome of a single check, details on success or the problem on failure
+pub struct Check {
+    pub name: &'static str,
+    pub result: Result<String, String>,
+    pub hint: &'static str,
+}
+
+pub fn check_config(path: &Path, loaded: Result<(), String>) -> Check {
+    Check {
+        name: "Config file",
+        result: loaded.map(|_| path.display().to_string()),
+        hint: "fix the YAML syntax or delete the file to recreate the default config",
+    }
+}
+
+pub fn check_portfolio_file(filename: &str) -> Check {
+    let result = if filename.ends_with(".gpg") {
+        if Path::new(filename).exists() {
+            Ok(format!("{} (encrypted, not parsed)", filename))
+        } else {
+            Err(format!("{} does not exist", filename))
+        }
+    } else {
+        std::fs::read_to_string(filename)
+            .map_err(|e| format!("Error reading {}: {}", filename, e))
+            .and_then(|data| {
+                serde_json::from_str::<Vec<PortfolioPosition>>(&data)
+                    .map_err(|e| format!("{} is not a valid portfolio: {}", filename, e))
+            })
+            .map(|positions| format!("{} ({} positions)", filename, positions.len()))
+    };
+
+    Check {
+        name: "Portfolio file",
+        result,
+        hint: "set `portfolio_file` in the config or pass the file as an argument, see example_data.json for the format",
+    }
+}
+
+// Opens an existing database without writing to it, a missing one is
+// not created (the first `balances` run does that)
+pub fn check_database(data_dir: Option<&Path>) -> Check {
+    let result = match data_dir {
+        None => Err("Could not determine a data directory".to_string()),
+        Some(data_dir) => {
+            let path = db_path(data_dir);
+            if path.exists() {
+                sled::open(&path)
+                    .map(|db| format!("{} ({} recorded balances)", path.display(), db.len()))
+                    .map_err(|e| format!("{}: {}", path.display(), e))
+            } else {
+                Ok(format!("{} (not created yet)", path.display()))
+            }
+        }
+    };
+
+    Check {
+        name: "Database",
+        result,
+        hint: "set `data_dir` to a writable directory, make sure no other portfolio_rs is running",
+    }
+}
+
+pub async fn check_prices(source: &impl PriceSource) -> Check {
+    let result = match source.get_quote_prices(TEST_TICKER).await {
+        Ok(quotes) if !quotes.closes.is_empty() => Ok(format!("got a price for {}", TEST_TICKER)),
+        Ok(_) => Err(format!("no prices for {}", TEST_TICKER)),
+        Err(e) => Err(e.to_string()),
+    };
+
+    Check {
+        name: "Yahoo Finance",
+        result,
+        hint: "check your internet connection and proxy settings",
+    }
+}
+
+pub fn check_gpg() -> Check {
+    let result = std::process::Command::new("gpg")
+        .arg("--version")
+        .output()
+        .map_err(|e| format!("gpg not found: {}", e))
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .next()
+                .unwrap_or("gpg")
+                .to_string()
+        });
+
+    Check {
+        name: "GnuPG",
+        result,
+        hint: "install GnuPG to use encrypted portfolio files",
+    }
+}
+
+// Prints one line per check, failed checks come with a hint.
+// Returns whether all checks passed.
+pub fn print_report(checks: &[Check]) -> bool {
+    for check in checks {
+        match &check.result {
+            Ok(details) => println!("[{}] {}: {}", " ok ".green(), check.name, details),
+            Err(problem) => {
+                println!("[{}] {}: {}", "FAIL".red(), check.name, problem);
+                println!("       hint: {}", check.hint);
+            }
+        }
+    }
+
+    let failed = checks.iter().filter(|c| c.result.is_err()).count();
+    if failed == 0 {
+        println!("All checks passed.");
+    } else {
+        println!("{} of {} checks failed.", failed, checks.len());
+    }
+    failed == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::MockSource;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_check_portfolio_file() {
+        let check = check_portfolio_file("example_data.json");
+        assert!(check.result.unwrap().starts_with("example_data.json ("));
+
+        let check = check_portfolio_file("does_not_exist.json");
+        assert!(check.result.is_err());
+
+        let check = check_portfolio_file("Cargo.toml");
+        assert!(check.result.unwrap_err().contains("not a valid portfolio"));
+    }
+
+    #[test]
+    fn test_check_database() {
+        let dir = std::env::temp_dir().join(format!("portfolio_doctor_{}", std::process::id()));
+        let check = check_database(Some(&dir));
+        assert!(check.result.unwrap().ends_with("(not created yet)"));
+        assert!(!dir.exists());
+
+        let db = sled::open(db_path(&dir)).unwrap();
+        db.insert("2024-01-01 10:00:00", "100").unwrap();
+        db.flush().unwrap();
+        drop(db);
+        let check = check_database(Some(&dir));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(check.result.unwrap().ends_with("(1 recorded balances)"));
+
+        assert!(check_database(None).result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_check_prices() {
+        let source = MockSource {
+            prices: HashMap::from([(TEST_TICKER.to_string(), vec![100.0])]),
+            ..Default::default()
+        };
+        assert!(check_prices(&source).await.result.is_ok());
+        assert!(check_prices(&MockSource::default()).await.result.is_err());
+    }
+}
@@ -0,0 +1,148 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+// Separators for numbers shown in tables, machine readable output (csv, json)
+// is never localized
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NumberFormat {
+    // 1234567.89
+    #[default]
+    Plain,
+    // 1,234,567.89
+    Us,
+    // 1.234.567,89
+    Eu,
+}
+
+impl NumberFormat {
+    // Regroup a number formatted by Rust (e.g. "-1234.50")
+    pub fn localize(self, plain: &str) -> String {
+        let (group, decimal) = match self {
+            NumberFormat::Plain => return plain.to_string(),
+            NumberFormat::Us => (',', '.'),
+            NumberFormat::Eu => ('.', ','),
+        };
+        let (sign, digits) = match plain.strip_prefix('-') {
+            Some(digits) => ("-", digits),
+            None => ("", plain),
+        };
+        let (integer, fraction) = match digits.split_once('.') {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (digits, None),
+        };
+
+        let mut localized = sign.to_string();
+        for (i, digit) in integer.chars().enumerate() {
+            if i > 0 && (integer.len() - i) % 3 == 0 {
+                localized.push(group);
+            }
+            localized.push(digit);
+        }
+        if let Some(fraction) = fraction {
+            localized.push(decimal);
+            localized.push_str(fraction);
+        }
+        localized
+    }
+}
+
+// set from the config, used by all tables
+static NUMBER_FORMAT: Mutex<NumberFormat> = Mutex::new(NumberFormat::Plain);
+
+pub fn set_number_format(format: NumberFormat) {
+    *NUMBER_FORMAT.lock().unwrap() = format;
+}
+
+// Format a number with two decimals in the configured style
+pub fn format_number(value: f64) -> String {
+    NUMBER_FORMAT
+        .lock()
+        .unwrap()
+        .localize(&format!("{:.2}", value))
+}
+
+// Like format_number, positive values get a "+" sign
+pub fn format_signed(value: f64) -> String {
+    if value.is_sign_negative() {
+        format_number(value)
+    } else {
+        format!("+{}", format_number(value))
+    }
+}
+
+// Format an amount (see format_amount) in the configured style
+pub fn format_localized_amount(value: f64) -> String {
+    NUMBER_FORMAT
+        .lock()
+        .unwrap()
+        .localize(&format_amount(value))
+}
+
+// significant digits shown for amounts below 1
+const SIGNIFICANT_DIGITS: usize = 2;
+// upper bound for the decimals of tiny amounts
+const MAX_DECIMALS: usize = 12;
+
+// Format an amount in fixed-point notation (never "1.2e-7").
+// Values of 1 and above get two decimals, smaller values get as many
+// decimals as needed to show their significant digits.
+pub fn format_amount(value: f64) -> String {
+    let abs = value.abs();
+    let decimals = if abs == 0.0 || abs >= 1.0 || !abs.is_finite() {
+        2
+    } else {
+        let leading_zeros = (-abs.log10()).ceil() as usize - 1;
+        (leading_zeros + SIGNIFICANT_DIGITS).clamp(2, MAX_DECIMALS)
+    };
+    format!("{:.*}", decimals, value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_amount() {
+        assert_eq!(format_amount(0.0), "0.00");
+        assert_eq!(format_amount(2.0), "2.00");
+        assert_eq!(format_amount(1234.567), "1234.57");
+        assert_eq!(format_amount(0.5), "0.50");
+        assert_eq!(format_amount(0.01), "0.010");
+        assert_eq!(format_amount(-0.25), "-0.25");
+    }
+
+    #[test]
+    fn test_format_amount_tiny_values() {
+        // one satoshi and below
+        assert_eq!(format_amount(0.00000001), "0.000000010");
+        assert_eq!(format_amount(0.00000012), "0.00000012");
+        assert_eq!(format_amount(0.0000000012), "0.0000000012");
+        assert!(!format_amount(1.2e-7).contains('e'));
+        assert!(!format_amount(1e-20).contains('e'));
+    }
+
+    #[test]
+    fn test_number_format() {
+        let us = NumberFormat::Us;
+        assert_eq!(us.localize(&format!("{:.2}", 1234567.89)), "1,234,567.89");
+        assert_eq!(us.localize(&format!("{:.2}", 999.5)), "999.50");
+        assert_eq!(us.localize("-1234.00"), "-1,234.00");
+        assert_eq!(us.localize("123456"), "123,456");
+
+        let eu = NumberFormat::Eu;
+        assert_eq!(eu.localize(&format!("{:.2}", 1234567.89)), "1.234.567,89");
+        assert_eq!(eu.localize(&format!("{:.2}", 999.5)), "999,50");
+        assert_eq!(eu.localize("-1234.00"), "-1.234,00");
+        assert_eq!(eu.localize("0.000000010"), "0,000000010");
+
+        assert_eq!(NumberFormat::Plain.localize("1234567.89"), "1234567.89");
+    }
+
+    #[test]
+    fn test_format_signed() {
+        assert_eq!(format_signed(1.5), "+1.50");
+        assert_eq!(format_signed(0.0), "+0.00");
+        assert_eq!(format_signed(-2.345), "-2.35");
+    }
+}
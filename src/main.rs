@@ -1,27 +1,119 @@
+use std::collections::HashMap;
 use std::fs::read_to_string;
+use std::io::{IsTerminal, Write};
 
+use crate::component::components_help;
+use crate::component::{Component, DisabledComponents};
+use crate::corporate_action::apply_corporate_action;
+use crate::corporate_action::CorporateAction;
+use crate::corporate_action::JsonFormat;
+use crate::db::open_db;
+use crate::db::print_allocation_drift;
+use crate::db::print_balance_history;
+use crate::db::store_balance_in_db;
+use crate::format::NumberFormat;
+use crate::output::info;
+use crate::portfolio::AllocationBand;
+use crate::portfolio::AllocationSort;
+use crate::portfolio::PiePalette;
 use crate::portfolio::Portfolio;
+use crate::position::apply_names;
 use crate::position::from_string;
 use crate::position::handle_position;
+use crate::position::merge_duplicates;
+use crate::position::validate_portfolio_json;
+use crate::position::validate_positions;
+use crate::position::CachedSource;
+use crate::position::PortfolioPosition;
+use crate::position::PriceSource;
+use crate::position::YahooSource;
 
-use clap::{arg, Command};
+use clap::{arg, ArgMatches, Command};
 use serde::Deserialize;
 use serde::Serialize;
 
+mod component;
+mod corporate_action;
+mod db;
+mod doctor;
+mod format;
+mod output;
 mod portfolio;
 mod position;
 
+// placeholder written to a freshly created config file
+const DEFAULT_PORTFOLIO_FILE: &str = "/home/Joe/portfolio.json";
+// last known quotes, stored next to the database
+const QUOTE_CACHE_FILE: &str = "quote_cache.json";
+
 #[derive(Serialize, Deserialize)]
 struct Config {
     portfolio_file: String,
     currency: String,
+    #[serde(default)]
+    allocation_bands: HashMap<String, AllocationBand>,
+    #[serde(default)]
+    allocation_sort: AllocationSort,
+    #[serde(default)]
+    allocation_order: Vec<String>,
+    #[serde(default)]
+    data_dir: Option<String>,
+    #[serde(default)]
+    show_tickers: bool,
+    #[serde(default = "default_asset_class")]
+    default_asset_class: String,
+    #[serde(default)]
+    json_format: JsonFormat,
+    #[serde(default)]
+    pie_palette: PiePalette,
+    #[serde(default)]
+    group_cash_by_currency: bool,
+    #[serde(default)]
+    targets: HashMap<String, f64>,
+    // seconds a cached quote is used without asking Yahoo Finance again
+    #[serde(default = "default_quote_cache_ttl")]
+    quote_cache_ttl: u64,
+    // ticker the performance is compared against, empty to disable
+    #[serde(default = "default_benchmark")]
+    benchmark: String,
+    // display names per ticker, e.g. "EUNL.DE: MSCI World"
+    #[serde(default)]
+    names: HashMap<String, String>,
+    #[serde(default)]
+    number_format: NumberFormat,
+}
+
+fn default_asset_class() -> String {
+    "Stocks".to_string()
+}
+
+fn default_quote_cache_ttl() -> u64 {
+    300
+}
+
+fn default_benchmark() -> String {
+    "^GSPC".to_string()
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
-            portfolio_file: "/home/Joe/portfolio.json".to_string(),
+            portfolio_file: DEFAULT_PORTFOLIO_FILE.to_string(),
             currency: "EUR".to_string(),
+            allocation_bands: HashMap::new(),
+            allocation_sort: AllocationSort::default(),
+            allocation_order: Vec::new(),
+            data_dir: None,
+            show_tickers: false,
+            default_asset_class: default_asset_class(),
+            json_format: JsonFormat::default(),
+            pie_palette: PiePalette::default(),
+            group_cash_by_currency: false,
+            targets: HashMap::new(),
+            quote_cache_ttl: default_quote_cache_ttl(),
+            benchmark: default_benchmark(),
+            names: HashMap::new(),
+            number_format: NumberFormat::default(),
         }
     }
 }
@@ -32,10 +124,26 @@ fn cli() -> Command {
         .author("Markus Zoppelt")
         .arg_required_else_help(true)
         .allow_external_subcommands(true)
+        .arg(arg!(-c --config <PATH> "Use this config file instead of the default one").global(true))
+        .arg(arg!(--"no-db" "Do not read or write the balance history database").global(true))
+        .arg(arg!(--tickers "Show ticker symbols instead of names").global(true))
+        .arg(
+            arg!(--offline "Use the cached quotes instead of asking Yahoo Finance")
+                .global(true),
+        )
+        .arg(
+            arg!(--"merge-duplicates" "Merge positions with the same ticker across portfolio files")
+                .global(true),
+        )
+        .arg(
+            arg!(--"passphrase-file" <FILE> "File with the passphrase for encrypted (.gpg) portfolio files")
+                .global(true),
+        )
+        .arg(arg!(-q --quiet "Only print the requested data and errors").global(true))
         .subcommand(Command::new("config").about("Print the path to the config file"))
         .subcommand(
-            Command::new("balances")
-                .about("Show the current balances of your portfolio")
+            Command::new("doctor")
+                .about("Check your config, portfolio file, database and network")
                 .arg(
                     arg!(<FILE> "JSON file with your positions")
                         .required(false)
@@ -43,33 +151,192 @@ fn cli() -> Command {
                 ),
         )
         .subcommand(
-            Command::new("allocation")
-                .about("Show the current allocation of your portfolio")
+            Command::new("validate")
+                .about("Check your portfolio file for mistakes without fetching prices")
                 .arg(
                     arg!(<FILE> "JSON file with your positions")
                         .required(false)
                         .default_value(""),
+                )
+                .after_help("Exits with 1 if any problems were found."),
+        )
+        .subcommand(
+            Command::new("balances")
+                .about("Show the current balances of your portfolio")
+                .after_help(NETWORK_EXIT_CODES)
+                .arg(arg!([FILE]... "JSON files with your positions"))
+                .arg(
+                    arg!(--watch <SECONDS> "Refresh the table every SECONDS until Ctrl-C")
+                        .value_parser(clap::value_parser!(u64).range(1..)),
+                )
+                .arg(
+                    arg!(--top <N> "Show only the N largest positions and sum up the rest")
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(
+                    arg!(--format <FORMAT> "Output format")
+                        .value_parser(["table", "csv", "json"])
+                        .default_value("table"),
+                )
+                .arg(
+                    arg!(--disable <COMPONENTS> "Comma separated list of columns to hide")
+                        .value_parser(DisabledComponents::from_list)
+                        .long_help(format!(
+                            "Comma separated list of columns to hide:\n{}",
+                            components_help()
+                        )),
+                )
+                .arg(
+                    arg!(--fields <COMPONENTS> "Comma separated list of the only columns to show")
+                        .value_parser(DisabledComponents::from_fields)
+                        .long_help(format!(
+                            "Comma separated list of the only columns to show, overrides --disable:\n{}",
+                            components_help()
+                        )),
                 ),
         )
         .subcommand(
-            Command::new("performance")
-                .about("Show the performance of your portfolio")
+            Command::new("allocation")
+                .about("Show the current allocation of your portfolio")
+                .after_help(NETWORK_EXIT_CODES)
+                .arg(arg!([FILE]... "JSON files with your positions"))
+                .arg(arg!(--"no-cash" "Leave out cash to show only the invested value"))
+                .arg(
+                    arg!(--"min-slice" <PERCENT> "Combine positions below this share of the total into \"Other\" in the pie chart")
+                        .value_parser(clap::value_parser!(f64))
+                        .default_value("0"),
+                ),
+        )
+        .subcommand(
+            Command::new("corporate-action")
+                .about("Record a merger, spinoff or split in your portfolio file")
                 .arg(
                     arg!(<FILE> "JSON file with your positions")
                         .required(false)
                         .default_value(""),
+                )
+                .arg(arg!(--from <TICKER> "Ticker of the affected position").required(true))
+                .arg(arg!(--to <TICKER> "Ticker of the resulting position").required(true))
+                .arg(
+                    arg!(--ratio <RATIO> "New shares received per old share")
+                        .value_parser(clap::value_parser!(f64))
+                        .default_value("1"),
+                )
+                .arg(
+                    arg!(--cash <AMOUNT> "Cash received per old share")
+                        .value_parser(clap::value_parser!(f64))
+                        .default_value("0"),
+                )
+                .arg(arg!(--keep "Keep the old position (e.g. for a spinoff)")),
+        )
+        .subcommand(
+            Command::new("drift")
+                .about("Show how your allocation drifted based on the stored balances")
+                .arg(
+                    arg!(--months <MONTHS> "Number of months to look back")
+                        .value_parser(clap::value_parser!(u32))
+                        .default_value("6"),
                 ),
         )
+        .subcommand(
+            Command::new("history")
+                .about("Plot the balances recorded by the balances subcommand")
+                .arg(arg!(--since <DATE> "Only show balances since this date (YYYY-MM-DD)"))
+                .arg(
+                    arg!(--limit <N> "Only show the last N balances")
+                        .value_parser(clap::value_parser!(usize)),
+                ),
+        )
+        .subcommand(
+            Command::new("holdings")
+                .about("Print your portfolio with live prices as JSON")
+                .arg(arg!([FILE]... "JSON files with your positions")),
+        )
+        .subcommand(
+            Command::new("rebalance")
+                .about("Show the trades needed to rebalance your portfolio")
+                .arg(arg!([FILE]... "JSON files with your positions"))
+                .arg(arg!(--"equal-weight" "Give every non-cash position the same weight")),
+        )
+        .subcommand(
+            Command::new("performance")
+                .about("Show the performance of your portfolio")
+                .after_help(NETWORK_EXIT_CODES)
+                .arg(arg!([FILE]... "JSON files with your positions"))
+                .arg(arg!(--json "Print the performance report as JSON"))
+                .arg(arg!(--"no-cash" "Leave out cash to show only the invested value")),
+        )
 }
 
-// returns a porfolio with the latest quotes from json data
-async fn create_live_portfolio(positions_str: String) -> Portfolio {
-    let positions = from_string(&positions_str);
+// How many quotes could be updated, reflected in the exit code
+#[derive(Debug, PartialEq)]
+enum NetworkStatus {
+    Connected,
+    Partial,
+    Disconnected,
+}
+
+impl NetworkStatus {
+    // `failed` of `total` securities have no current price
+    fn from_counts(total: usize, failed: usize) -> Self {
+        let failed = failed.min(total);
+        if failed == 0 {
+            NetworkStatus::Connected
+        } else if failed >= total {
+            NetworkStatus::Disconnected
+        } else {
+            NetworkStatus::Partial
+        }
+    }
+
+    fn exit_code(&self) -> i32 {
+        match self {
+            NetworkStatus::Connected => 0,
+            NetworkStatus::Disconnected => 2,
+            NetworkStatus::Partial => 3,
+        }
+    }
+}
+
+// Securities without a price or with an expired one count as failed,
+// expired FX rates do not
+fn network_status<S>(
+    securities: usize,
+    portfolio: &Portfolio,
+    source: &CachedSource<S>,
+) -> NetworkStatus {
+    let unpriced = securities.saturating_sub(portfolio.count_priced());
+    let stale = portfolio.count_stale(&source.stale_tickers());
+    NetworkStatus::from_counts(securities, unpriced + stale)
+}
+
+const NETWORK_EXIT_CODES: &str = "Exits with 2 if no prices could be updated and 3 if only some could be updated (e.g. when offline or for unknown tickers).";
+
+// returns a porfolio with the latest quotes from json data,
+// dividends are only fetched when `fetch_dividends` is set
+async fn create_live_portfolio<S>(
+    positions: Vec<PortfolioPosition>,
+    source: &S,
+    currency: &str,
+    fetch_dividends: bool,
+) -> Portfolio
+where
+    S: PriceSource + Clone + Send + Sync + 'static,
+{
+    for error in validate_positions(&positions) {
+        info(format!("Warning: {}", error));
+    }
     let mut portfolio = Portfolio::new();
     // move tasks into the async closure passed to tokio::spawn()
     let tasks: Vec<_> = positions
         .into_iter()
-        .map(move |mut position| tokio::spawn(async move { handle_position(&mut position).await }))
+        .map(move |mut position| {
+            let currency = currency.to_string();
+            let source = source.clone();
+            tokio::spawn(async move {
+                handle_position(&mut position, &source, &currency, fetch_dividends).await
+            })
+        })
         .collect();
 
     for task in tasks {
@@ -85,90 +352,566 @@ async fn create_live_portfolio(positions_str: String) -> Portfolio {
     portfolio
 }
 
-// TODO: change this to store entire portfolio in DB
-fn store_balance_in_db(portfolio: &Portfolio) {
-    let db = sled::open("database").unwrap();
-    let curr_value = portfolio.get_total_value();
-    let curr_time = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+// On first run the config still points at the placeholder file.
+// Offer to create a starter portfolio from the example data and
+// point the config at it. Returns the path of the new portfolio file.
+// Never asks when stdin is not a terminal (scripts, pipes), the prompt
+// goes to stderr so it does not end up in csv or json output.
+fn onboard(cfg: &mut Config, config_path: &std::path::Path) -> Option<String> {
+    if !std::io::stdin().is_terminal() {
+        return None;
+    }
+    let path = std::env::current_dir().ok()?.join("portfolio.json");
+
+    if path.exists() {
+        eprint!(
+            "No portfolio file configured. Use the existing {}? [y/N] ",
+            path.display()
+        );
+    } else {
+        eprint!(
+            "No portfolio file configured. Create a starter portfolio at {}? [y/N] ",
+            path.display()
+        );
+    }
+    std::io::stderr().flush().ok()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer).ok()?;
+    if !answer.trim().eq_ignore_ascii_case("y") {
+        return None;
+    }
 
-    db.insert(curr_time, curr_value.to_string().as_bytes())
-        .unwrap();
+    if !path.exists() {
+        if let Err(e) = std::fs::write(&path, include_str!("../example_data.json")) {
+            eprintln!("Error creating {}: {}", path.display(), e);
+            return None;
+        }
+    }
 
-    // block until all operations are stable on disk
-    db.flush().unwrap();
+    cfg.portfolio_file = path.to_str()?.to_string();
+    if let Err(e) = confy::store_path(config_path, &*cfg) {
+        eprintln!("Error updating config: {}", e);
+    }
+    info(format!(
+        "Edit {} to add your own positions.",
+        cfg.portfolio_file
+    ));
+    Some(cfg.portfolio_file.clone())
 }
 
-fn open_encrpted_file(filename: String) -> String {
+// Columns hidden by --fields or --disable, --fields wins if both are given
+fn disabled_components(matches: &ArgMatches) -> DisabledComponents {
+    let fields = matches.get_one::<DisabledComponents>("fields");
+    let disable = matches.get_one::<DisabledComponents>("disable");
+    if fields.is_some() && disable.is_some() {
+        info("Warning: --fields overrides --disable");
+    }
+    fields.or(disable).cloned().unwrap_or_default()
+}
+
+// Reprint the balances table every `secs` seconds until Ctrl-C,
+// balances are not recorded in the database
+async fn watch_balances<S>(
+    positions: Vec<PortfolioPosition>,
+    source: &S,
+    currency: &str,
+    secs: u64,
+    show_tickers: bool,
+    disabled: &DisabledComponents,
+    top: Option<usize>,
+) where
+    S: PriceSource + Clone + Send + Sync + 'static,
+{
+    let stop = tokio::signal::ctrl_c();
+    tokio::pin!(stop);
+
+    loop {
+        let refresh = async {
+            let fetch_dividends = !disabled.is_disabled(Component::Dividend);
+            let portfolio =
+                create_live_portfolio(positions.clone(), source, currency, fetch_dividends).await;
+            // clear the screen and move the cursor to the top left corner
+            print!("\x1B[2J\x1B[H");
+            portfolio.print(true, show_tickers, disabled, top);
+            if !output::is_quiet() {
+                portfolio.print_daily_movers();
+                println!(
+                    "Updated at {}, press Ctrl-C to stop",
+                    chrono::Local::now().format("%H:%M:%S")
+                );
+            }
+            tokio::time::sleep(std::time::Duration::from_secs(secs)).await;
+        };
+        tokio::select! {
+            _ = &mut stop => break,
+            _ = refresh => (),
+        }
+    }
+    println!();
+}
+
+// environment variable that can hold the gpg passphrase for unattended runs
+const PASSPHRASE_ENV: &str = "PORTFOLIO_GPG_PASSPHRASE";
+
+// Returns the gpg passphrase from --passphrase-file or the environment.
+// Without either, gpg asks for it via gpg-agent.
+fn gpg_passphrase(passphrase_file: Option<&String>) -> Result<Option<String>, String> {
+    if let Some(path) = passphrase_file {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            if let Ok(metadata) = std::fs::metadata(path) {
+                if metadata.permissions().mode() & 0o077 != 0 {
+                    info(format!(
+                        "Warning: {} is readable by other users, consider `chmod 600 {}`",
+                        path, path
+                    ));
+                }
+            }
+        }
+        let passphrase = read_to_string(path)
+            .map_err(|e| format!("Error reading passphrase file {}: {}", path, e))?;
+        return Ok(Some(passphrase.trim_end_matches(['\r', '\n']).to_string()));
+    }
+
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        info(format!(
+            "Warning: passing the passphrase via {} is insecure, prefer --passphrase-file",
+            PASSPHRASE_ENV
+        ));
+        return Ok(Some(passphrase));
+    }
+    Ok(None)
+}
+
+fn open_encrpted_file(filename: String, passphrase: Option<&str>) -> Result<String, String> {
+    let mut command = std::process::Command::new("gpg");
+    if passphrase.is_some() {
+        command
+            .arg("--batch")
+            .arg("--pinentry-mode")
+            .arg("loopback")
+            .arg("--passphrase-fd")
+            .arg("0")
+            .stdin(std::process::Stdio::piped());
+    }
+    command
+        .arg("-d")
+        .arg(&filename)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped());
+
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(
+                "gpg not found — install GnuPG to use encrypted portfolio files".to_string(),
+            )
+        }
+        Err(e) => return Err(format!("Error running gpg: {}", e)),
+    };
+
+    if let (Some(passphrase), Some(mut stdin)) = (passphrase, child.stdin.take()) {
+        // gpg may already have quit (e.g. a missing file), its stderr explains why
+        writeln!(stdin, "{}", passphrase).ok();
+    }
+
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("Error running gpg: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "Failed to decrypt {}: {}",
+            filename,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    String::from_utf8(output.stdout).map_err(|e| format!("Error decoding {}: {}", filename, e))
+}
+
+// returns the (decrypted) content of a portfolio file
+fn load_portfolio(filename: &str, passphrase: Option<&str>) -> Result<String, String> {
     if filename.ends_with(".gpg") {
-        let output = std::process::Command::new("gpg")
-            .arg("-d")
-            .arg(filename)
-            .output()
-            .expect("failed to execute gpg process");
-        String::from_utf8(output.stdout).unwrap()
+        open_encrpted_file(filename.to_string(), passphrase)
     } else {
-        read_to_string(filename).unwrap()
+        read_to_string(filename).map_err(|_| format!("Error reading file: {}", filename))
+    }
+}
+
+// An explicit --config wins over the default location. Only the default
+// config is created on first run, a mistyped --config path is an error
+fn config_path(explicit: Option<&String>) -> Result<std::path::PathBuf, String> {
+    match explicit {
+        Some(path) if !std::path::Path::new(path).exists() => {
+            Err(format!("Config file {} does not exist", path))
+        }
+        Some(path) => Ok(std::path::PathBuf::from(path)),
+        None => confy::get_configuration_file_path("portfolio", "config")
+            .map_err(|e| format!("Could not determine the config location: {}", e)),
     }
 }
 
 #[tokio::main]
 async fn main() {
-    let cfg: Config = confy::load("portfolio", "config").unwrap();
-
     let matches = cli().get_matches();
 
+    let config_path = match config_path(matches.get_one::<String>("config")) {
+        Ok(path) => path,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let (mut cfg, config_error): (Config, _) = match confy::load_path(&config_path) {
+        Ok(cfg) => (cfg, None),
+        Err(e) => (Config::default(), Some(e.to_string())),
+    };
+
+    // the doctor reports a broken config instead of giving up
+    if let Some(matches) = matches.subcommand_matches("doctor") {
+        let mut filename = matches.get_one::<String>("FILE").unwrap().to_string();
+        if filename.is_empty() {
+            filename.clone_from(&cfg.portfolio_file);
+        }
+        let data_dir = db::data_dir(cfg.data_dir.as_deref());
+
+        let mut checks = vec![
+            doctor::check_config(&config_path, config_error.map_or(Ok(()), Err)),
+            doctor::check_portfolio_file(&filename),
+            doctor::check_database(data_dir.as_deref()),
+            doctor::check_prices(&YahooSource).await,
+        ];
+        if filename.ends_with(".gpg") {
+            checks.push(doctor::check_gpg());
+        }
+        if !doctor::print_report(&checks) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(e) = config_error {
+        eprintln!("Error loading config {}: {}", config_path.display(), e);
+        return;
+    }
+
     if let Some(_matches) = matches.subcommand_matches("config") {
         println!(
             "Your config file is located here: \n{}",
-            confy::get_configuration_file_path("portfolio", "config")
-                .unwrap()
-                .to_str()
-                .unwrap()
+            config_path.to_str().unwrap()
         );
     }
 
-    for subcommand in ["balances", "allocation", "performance"].iter() {
-        if let Some(matches) = matches.subcommand_matches(subcommand) {
-            let mut filename = String::new();
+    output::set_quiet(matches.get_flag("quiet"));
+    format::set_number_format(cfg.number_format);
+    let offline = matches.get_flag("offline");
+    let no_db = matches.get_flag("no-db");
+    let show_tickers = cfg.show_tickers || matches.get_flag("tickers");
+    let data_dir = db::data_dir(cfg.data_dir.as_deref());
+    if data_dir.is_none() {
+        info("Could not determine a data directory, history is disabled");
+    }
 
-            // try to get filename as argument
-            if let Ok(Some(f)) = matches.try_get_one::<String>("FILE") {
-                filename = f.to_string();
+    if let Some(matches) = matches.subcommand_matches("drift") {
+        if no_db {
+            eprintln!("The allocation history is stored in the database, remove --no-db");
+            return;
+        }
+        let months = *matches.get_one::<u32>("months").unwrap();
+        let since = chrono::Local::now()
+            .checked_sub_months(chrono::Months::new(months))
+            .unwrap()
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string();
+        if let Some(db) = data_dir.as_deref().and_then(open_db) {
+            print_allocation_drift(&db, &since);
+        }
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("history") {
+        if no_db {
+            eprintln!("The balance history is stored in the database, remove --no-db");
+            return;
+        }
+        let since = matches
+            .get_one::<String>("since")
+            .map(|s| s.as_str())
+            .unwrap_or("");
+        let limit = matches.get_one::<usize>("limit").copied();
+        if let Some(db) = data_dir.as_deref().and_then(open_db) {
+            print_balance_history(&db, since, limit);
+        }
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("corporate-action") {
+        let mut filename = matches.get_one::<String>("FILE").unwrap().to_string();
+        if filename.is_empty() {
+            filename.clone_from(&cfg.portfolio_file);
+        }
+        if filename.ends_with(".gpg") {
+            eprintln!(
+                "Encrypted portfolio files cannot be modified, decrypt {} first",
+                filename
+            );
+            return;
+        }
+
+        let action = CorporateAction {
+            from: matches.get_one::<String>("from").unwrap().to_string(),
+            to: matches.get_one::<String>("to").unwrap().to_string(),
+            ratio: *matches.get_one::<f64>("ratio").unwrap(),
+            cash_per_share: *matches.get_one::<f64>("cash").unwrap(),
+            keep: matches.get_flag("keep"),
+        };
+        let result = read_to_string(&filename)
+            .map_err(|_| format!("Error reading file: {}", filename))
+            .and_then(|data| apply_corporate_action(&data, &action, cfg.json_format))
+            .and_then(|data| {
+                std::fs::write(&filename, data)
+                    .map_err(|e| format!("Error writing {}: {}", filename, e))
+            });
+        match result {
+            Ok(()) => info(format!("Updated {}", filename)),
+            Err(e) => eprintln!("{}", e),
+        }
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("validate") {
+        let mut filename = matches.get_one::<String>("FILE").unwrap().to_string();
+        if filename.is_empty() {
+            filename.clone_from(&cfg.portfolio_file);
+        }
+        let passphrase = if filename.ends_with(".gpg") {
+            match gpg_passphrase(matches.get_one::<String>("passphrase-file")) {
+                Ok(passphrase) => passphrase,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
             }
+        } else {
+            None
+        };
+        let problems = match load_portfolio(&filename, passphrase.as_deref()) {
+            Ok(data) => validate_portfolio_json(&data),
+            Err(e) => vec![e],
+        };
+        if problems.is_empty() {
+            println!("{}: no problems found", filename);
+            return;
+        }
+        for problem in &problems {
+            println!("{}: {}", filename, problem);
+        }
+        std::process::exit(1);
+    }
+
+    for subcommand in [
+        "balances",
+        "allocation",
+        "performance",
+        "holdings",
+        "rebalance",
+    ]
+    .iter()
+    {
+        if let Some(matches) = matches.subcommand_matches(subcommand) {
+            // try to get filenames as arguments
+            let mut filenames: Vec<String> = matches
+                .get_many::<String>("FILE")
+                .map(|files| files.cloned().collect())
+                .unwrap_or_default();
             // if no argument is given, try to get filename from config
-            if filename.is_empty() {
-                filename.clone_from(&cfg.portfolio_file);
+            if filenames.is_empty() {
+                let mut filename = cfg.portfolio_file.clone();
+
+                // on first run, offer to create a starter portfolio
+                if filename == DEFAULT_PORTFOLIO_FILE && !std::path::Path::new(&filename).exists() {
+                    filename = onboard(&mut cfg, &config_path).unwrap_or_default();
+                }
+                if !filename.is_empty() {
+                    filenames.push(filename);
+                }
             }
-            // if no argument and no config is given, print help
-            if filename.is_empty() {
-                cli().print_help().unwrap();
-                return;
+            // if no argument and no config is given, there is nothing to show
+            if filenames.is_empty() {
+                eprintln!(
+                    "No portfolio file given, pass FILE or set portfolio_file in {}",
+                    config_path.display()
+                );
+                std::process::exit(1);
             }
-            let positions_str = if filename.ends_with(".gpg") {
-                open_encrpted_file(filename.to_string())
-            } else if let Ok(s) = read_to_string(&filename) {
-                s
+            let passphrase = if filenames.iter().any(|f| f.ends_with(".gpg")) {
+                match gpg_passphrase(matches.get_one::<String>("passphrase-file")) {
+                    Ok(passphrase) => passphrase,
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        return;
+                    }
+                }
             } else {
-                eprintln!("Error reading file: {}", filename);
-                return;
+                None
             };
+            let mut positions = Vec::new();
+            for filename in &filenames {
+                let loaded = load_portfolio(filename, passphrase.as_deref()).and_then(|s| {
+                    from_string(&s, &cfg.default_asset_class)
+                        .map_err(|e| format!("Error reading {}: {}", filename, e))
+                });
+                match loaded {
+                    Ok(loaded) => positions.extend(loaded),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            if matches.get_flag("merge-duplicates") {
+                positions = merge_duplicates(positions);
+            }
+            apply_names(&mut positions, &cfg.names);
+
+            let watch = matches.try_get_one::<u64>("watch").ok().flatten().copied();
+            let mut source = CachedSource::load(
+                YahooSource,
+                data_dir.as_ref().map(|dir| dir.join(QUOTE_CACHE_FILE)),
+                // every refresh should get new quotes
+                watch.map_or(cfg.quote_cache_ttl, |secs| secs.min(cfg.quote_cache_ttl)),
+            );
+            if offline {
+                source = source.offline();
+            }
 
-            let portfolio = create_live_portfolio(positions_str).await;
+            if let Some(secs) = watch {
+                let disabled = disabled_components(matches);
+                watch_balances(
+                    positions,
+                    &source,
+                    &cfg.currency,
+                    secs,
+                    show_tickers,
+                    &disabled,
+                    matches.get_one::<usize>("top").copied(),
+                )
+                .await;
+                if let Err(e) = source.save() {
+                    info(format!("Could not store the quote cache: {}", e));
+                }
+                return;
+            }
+
+            let securities = positions
+                .iter()
+                .filter(|p| p.get_ticker().is_some())
+                .count();
+            // only the balances table shows the dividend column
+            let fetch_dividends = *subcommand == "balances"
+                && matches.get_one::<String>("format").map(|s| s.as_str()) == Some("table")
+                && !disabled_components(matches).is_disabled(Component::Dividend);
+            let portfolio =
+                create_live_portfolio(positions, &source, &cfg.currency, fetch_dividends).await;
+            let status = network_status(securities, &portfolio, &source);
+            let portfolio = match matches.try_get_one::<bool>("no-cash") {
+                Ok(Some(true)) => portfolio.without_cash(),
+                _ => portfolio,
+            };
+            if let Err(e) = source.save() {
+                info(format!("Could not store the quote cache: {}", e));
+            }
 
             match subcommand as &str {
                 "balances" => {
-                    portfolio.print(true);
-                    store_balance_in_db(&portfolio);
+                    let disabled = disabled_components(matches);
+                    match matches.get_one::<String>("format").unwrap().as_str() {
+                        "csv" => print!("{}", portfolio.to_csv()),
+                        "json" => {
+                            let holdings = portfolio.get_holdings(&cfg.currency);
+                            println!("{}", serde_json::to_string_pretty(&holdings).unwrap());
+                        }
+                        _ => {
+                            if disabled.all_disabled() {
+                                info("All columns are disabled, only names are shown");
+                            }
+                            let top = matches.get_one::<usize>("top").copied();
+                            portfolio.print(true, show_tickers, &disabled, top);
+                            if !output::is_quiet() {
+                                portfolio.print_daily_movers();
+                            }
+                        }
+                    }
+                    // balances without live prices would distort the history
+                    let db = if no_db || offline {
+                        None
+                    } else {
+                        data_dir.as_deref().and_then(open_db)
+                    };
+                    if let Some(db) = db {
+                        if let Err(e) = store_balance_in_db(&db, &portfolio, &cfg.currency) {
+                            info(format!("Could not store balance in database: {}", e));
+                        }
+                    }
                 }
                 "allocation" => {
-                    portfolio.draw_pie_chart();
-                    portfolio.print_allocation();
+                    portfolio.draw_pie_chart(
+                        show_tickers,
+                        cfg.pie_palette,
+                        *matches.get_one::<f64>("min-slice").unwrap(),
+                    );
+                    portfolio.print_allocation(
+                        cfg.allocation_sort,
+                        &cfg.allocation_order,
+                        &cfg.allocation_bands,
+                        cfg.group_cash_by_currency,
+                    );
+                }
+                "rebalance" => {
+                    if matches.get_flag("equal-weight") {
+                        portfolio.print_equal_weight_rebalance(show_tickers);
+                    } else if !cfg.targets.is_empty() {
+                        portfolio.print_target_rebalance(&cfg.targets);
+                    } else {
+                        eprintln!(
+                            "Choose a rebalancing strategy, e.g. --equal-weight or set `targets` in the config"
+                        );
+                    }
+                }
+                "holdings" => {
+                    let holdings = portfolio.get_holdings(&cfg.currency);
+                    println!("{}", serde_json::to_string_pretty(&holdings).unwrap());
                 }
                 "performance" => {
-                    portfolio.print_performance().await;
+                    // stored balances include cash, comparing them against
+                    // the invested value only would be meaningless
+                    let no_cash = matches.get_flag("no-cash");
+                    let db = if no_db || no_cash {
+                        None
+                    } else {
+                        data_dir.as_deref().and_then(open_db)
+                    };
+                    if matches.get_flag("json") {
+                        let report = portfolio
+                            .performance_report(db.as_ref(), &source, &cfg.currency, &cfg.benchmark)
+                            .await;
+                        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+                    } else {
+                        portfolio
+                            .print_performance(db.as_ref(), &source, &cfg.currency, &cfg.benchmark)
+                            .await;
+                    }
                 }
                 _ => (),
             }
+
+            // scripts can tell stale output apart
+            if matches!(*subcommand, "balances" | "allocation" | "performance")
+                && status != NetworkStatus::Connected
+            {
+                std::process::exit(status.exit_code());
+            }
         }
     }
 }
@@ -176,19 +919,174 @@ async fn main() {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::string::ParseError;
+    use crate::component::Component;
+    use crate::position::MockSource;
 
     #[test]
     fn test_cli() {
         let matches = cli().get_matches_from(vec!["portfolio_rs", "balances", "example_data.json"]);
         assert_eq!(matches.subcommand_name(), Some("balances"));
+        let balances = matches.subcommand_matches("balances").unwrap();
+        assert_eq!(
+            balances.get_one::<String>("format").map(|s| s.as_str()),
+            Some("table")
+        );
+        assert!(cli()
+            .try_get_matches_from(vec!["portfolio_rs", "balances", "--format", "xml"])
+            .is_err());
+
+        let matches = cli().get_matches_from(vec![
+            "portfolio_rs",
+            "allocation",
+            "broker1.json",
+            "broker2.json",
+            "--merge-duplicates",
+        ]);
+        let allocation = matches.subcommand_matches("allocation").unwrap();
+        let files: Vec<&String> = allocation.get_many::<String>("FILE").unwrap().collect();
+        assert_eq!(files, ["broker1.json", "broker2.json"]);
+        assert!(allocation.get_flag("merge-duplicates"));
+
+        let matches = cli().get_matches_from(vec![
+            "portfolio_rs",
+            "balances",
+            "--no-db",
+            "--quiet",
+            "--tickers",
+            "--offline",
+            "--config",
+            "work.yml",
+        ]);
+        assert!(matches.get_flag("no-db"));
+        assert!(matches.get_flag("quiet"));
+        assert!(matches.get_flag("tickers"));
+        assert!(matches.get_flag("offline"));
+        assert_eq!(
+            matches.get_one::<String>("config").map(|s| s.as_str()),
+            Some("work.yml")
+        );
+
+        let matches = cli().get_matches_from(vec![
+            "portfolio_rs",
+            "balances",
+            "--disable",
+            "balance",
+            "--fields",
+            "balance,weight",
+        ]);
+        let balances = matches.subcommand_matches("balances").unwrap();
+        let disabled = disabled_components(balances);
+        assert!(!disabled.is_disabled(Component::Balance));
+        assert!(disabled.is_disabled(Component::Amount));
+    }
+
+    #[test]
+    fn test_network_status() {
+        assert_eq!(NetworkStatus::from_counts(0, 0), NetworkStatus::Connected);
+        assert_eq!(NetworkStatus::from_counts(5, 0), NetworkStatus::Connected);
+        assert_eq!(NetworkStatus::from_counts(5, 2), NetworkStatus::Partial);
+        assert_eq!(
+            NetworkStatus::from_counts(5, 5),
+            NetworkStatus::Disconnected
+        );
+        assert_eq!(NetworkStatus::from_counts(2, 3).exit_code(), 2);
+        assert_eq!(NetworkStatus::from_counts(5, 2).exit_code(), 3);
+    }
+
+    #[test]
+    fn test_config_path_missing() {
+        let result = config_path(Some(&"work.ymll".to_string()));
+        assert_eq!(
+            result,
+            Err("Config file work.ymll does not exist".to_string())
+        );
+        assert!(!std::path::Path::new("work.ymll").exists());
+
+        let result = config_path(Some(&"Cargo.toml".to_string()));
+        assert_eq!(result, Ok(std::path::PathBuf::from("Cargo.toml")));
+    }
+
+    #[test]
+    fn test_load_portfolio_missing_file() {
+        let result = load_portfolio("does_not_exist.json", None);
+        assert_eq!(
+            result,
+            Err("Error reading file: does_not_exist.json".to_string())
+        );
+    }
+
+    #[test]
+    fn test_load_portfolio_decrypt_failure() {
+        let result = load_portfolio("does_not_exist.json.gpg", Some("secret")).unwrap_err();
+        // depending on whether gpg is installed
+        assert!(
+            result.starts_with("Failed to decrypt does_not_exist.json.gpg: ")
+                || result.starts_with("gpg not found"),
+            "{}",
+            result
+        );
     }
 
     #[tokio::test]
     async fn test_create_live_portfolio() {
         let positions_str = std::fs::read_to_string("example_data.json").unwrap();
-        let portfolio = create_live_portfolio(positions_str).await;
-        let x: Result<Portfolio, ParseError> = Ok(portfolio);
-        assert!(x.is_ok());
+        let positions = from_string(&positions_str, "Stocks").unwrap();
+        let mut source = MockSource::default();
+        for (ticker, price) in [
+            ("SPY", 500.0),
+            ("TLT", 90.0),
+            ("GSG", 20.0),
+            ("GLD", 200.0),
+            ("BTC-USD", 60000.0),
+            ("USDEUR=X", 0.5),
+        ] {
+            source.prices.insert(ticker.to_string(), vec![price]);
+            source
+                .currencies
+                .insert(ticker.to_string(), "USD".to_string());
+        }
+        // positions without a name get it from the source
+        for (ticker, name) in [("SPY", "SPDR S&P 500"), ("GLD", "SPDR Gold Shares")] {
+            source.names.insert(ticker.to_string(), name.to_string());
+        }
+
+        let portfolio = create_live_portfolio(positions, &source, "EUR", false).await;
+        assert_eq!(portfolio.count_priced(), 5);
+        // 2220 USD in securities at 0.5 plus 200 EUR in cash
+        assert_eq!(portfolio.get_total_value(), 1310.0);
+    }
+
+    #[tokio::test]
+    async fn test_network_status_ignores_stale_fx_rates() {
+        let path = std::env::temp_dir().join(format!(
+            "portfolio_network_status_{}.json",
+            std::process::id()
+        ));
+        // cache a CAD rate, then let it expire with the source unable to refresh it
+        let source = MockSource {
+            prices: HashMap::from([("CADEUR=X".to_string(), vec![0.5])]),
+            ..Default::default()
+        };
+        let cached = CachedSource::load(source, Some(path.clone()), 300);
+        cached.get_quote_prices("CADEUR=X").await.unwrap();
+        cached.save().unwrap();
+
+        let source = MockSource {
+            prices: HashMap::from([("SHOP".to_string(), vec![100.0])]),
+            currencies: HashMap::from([("SHOP".to_string(), "CAD".to_string())]),
+            names: HashMap::from([("SHOP".to_string(), "Shopify".to_string())]),
+            ..Default::default()
+        };
+        let source = CachedSource::load(source, Some(path.clone()), 0);
+        let positions = from_string(r#"[{"Ticker": "SHOP", "Amount": 1}]"#, "Stocks").unwrap();
+        let portfolio = create_live_portfolio(positions, &source, "EUR", false).await;
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(portfolio.get_total_value(), 50.0);
+        assert!(source.stale_tickers().contains("CADEUR=X"));
+        assert_eq!(
+            network_status(1, &portfolio, &source),
+            NetworkStatus::Connected
+        );
     }
 }
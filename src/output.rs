@@ -0,0 +1,20 @@
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// set by --quiet, suppresses everything but the requested data and errors
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+// Print an informational message or warning to stderr unless in quiet mode
+pub fn info(message: impl Display) {
+    if !is_quiet() {
+        eprintln!("{}", message);
+    }
+}
@@ -1,9 +1,234 @@
-use crate::position::get_historic_price;
+use crate::component::Component;
+use crate::component::DisabledComponents;
+use crate::format::format_localized_amount;
+use crate::format::format_number;
+use crate::format::format_signed;
+use crate::output::info;
+use crate::position::get_fx_rate;
+use crate::position::major_currency;
 use crate::position::PortfolioPosition;
+use crate::position::PriceSource;
 use chrono::prelude::*;
 use colored::Colorize;
 use piechart::{Chart, Color};
-use std::collections::HashMap;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+// A tolerance band for the allocation of an asset class, in percent
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct AllocationBand {
+    pub min: f64,
+    pub max: f64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BandDrift {
+    Above,
+    Below,
+}
+
+impl AllocationBand {
+    // Returns the direction in which the percentage left the band, if it did
+    pub fn drift(&self, percentage: f64) -> Option<BandDrift> {
+        if percentage > self.max {
+            Some(BandDrift::Above)
+        } else if percentage < self.min {
+            Some(BandDrift::Below)
+        } else {
+            None
+        }
+    }
+}
+
+// Order in which asset classes are listed in the allocation
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AllocationSort {
+    #[default]
+    Percentage,
+    Alphabetical,
+    // follow the configured `allocation_order`, unlisted classes go last
+    Custom,
+}
+
+// Sort the allocation according to `sort`, ties are broken by name so
+// the order stays stable between runs
+pub fn sort_allocation(
+    allocation: &HashMap<String, f64>,
+    sort: AllocationSort,
+    order: &[String],
+) -> Vec<(String, f64)> {
+    let mut allocation_vec: Vec<(String, f64)> = allocation
+        .iter()
+        .map(|(class, percentage)| (class.to_string(), *percentage))
+        .collect();
+    allocation_vec.sort_by(|a, b| a.0.cmp(&b.0));
+
+    match sort {
+        AllocationSort::Percentage => {
+            allocation_vec.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        }
+        AllocationSort::Alphabetical => (),
+        AllocationSort::Custom => {
+            let position = |class: &str| {
+                order
+                    .iter()
+                    .position(|o| o.eq_ignore_ascii_case(class))
+                    .unwrap_or(order.len())
+            };
+            allocation_vec.sort_by_key(|(class, _)| position(class));
+        }
+    }
+    allocation_vec
+}
+
+// Colors used for the slices of the pie chart
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PiePalette {
+    // pick the widest palette the terminal supports
+    #[default]
+    Auto,
+    Basic,
+    Ansi256,
+    Truecolor,
+}
+
+impl PiePalette {
+    // Resolve `Auto` based on the COLORTERM and TERM environment variables
+    pub fn detect(self) -> PiePalette {
+        if self != PiePalette::Auto {
+            return self;
+        }
+        let colorterm = std::env::var("COLORTERM").unwrap_or_default();
+        let term = std::env::var("TERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            PiePalette::Truecolor
+        } else if term.contains("256color") {
+            PiePalette::Ansi256
+        } else {
+            PiePalette::Basic
+        }
+    }
+
+    // Returns `n` colors, adjacent colors are easy to tell apart
+    pub fn colors(self, n: usize) -> Vec<Color> {
+        const BASIC: [Color; 8] = [
+            Color::Red,
+            Color::Green,
+            Color::Blue,
+            Color::Yellow,
+            Color::Cyan,
+            Color::White,
+            Color::Purple,
+            Color::Black,
+        ];
+        const ANSI256: [u8; 16] = [
+            196, 46, 21, 226, 51, 201, 208, 93, 118, 33, 160, 220, 129, 39, 190, 166,
+        ];
+
+        match self.detect() {
+            PiePalette::Truecolor => (0..n)
+                .map(|i| {
+                    // step around the color wheel by the golden angle
+                    let (r, g, b) = hsv_to_rgb((i as f64 * 137.508) % 360.0, 0.75, 0.95);
+                    Color::RGB(r, g, b)
+                })
+                .collect(),
+            PiePalette::Ansi256 => (0..n)
+                .map(|i| Color::Fixed(ANSI256[i % ANSI256.len()]))
+                .collect(),
+            _ => (0..n).map(|i| BASIC[i % BASIC.len()]).collect(),
+        }
+    }
+}
+
+fn hsv_to_rgb(hue: f64, saturation: f64, value: f64) -> (u8, u8, u8) {
+    let c = value * saturation;
+    let x = c * (1.0 - ((hue / 60.0) % 2.0 - 1.0).abs());
+    let m = value - c;
+    let (r, g, b) = match hue as u32 {
+        0..=59 => (c, x, 0.0),
+        60..=119 => (x, c, 0.0),
+        120..=179 => (0.0, c, x),
+        180..=239 => (0.0, x, c),
+        240..=299 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let to_u8 = |v: f64| ((v + m) * 255.0).round() as u8;
+    (to_u8(r), to_u8(g), to_u8(b))
+}
+
+// A position with its live values, as printed by the holdings subcommand
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Holding {
+    pub name: String,
+    pub ticker: Option<String>,
+    pub asset_class: String,
+    pub amount: f64,
+    pub market_price: Option<f64>,
+    pub balance: f64,
+    pub allocation_percent: f64,
+}
+
+// The fully resolved portfolio, as printed by the holdings subcommand
+#[derive(Debug, Serialize)]
+pub struct Holdings {
+    pub currency: String,
+    pub total_value: f64,
+    pub liabilities: f64,
+    pub positions: Vec<Holding>,
+}
+
+// Quote a CSV field if it contains a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// number of best and worst positions listed in the performance report
+const TOP_MOVERS: usize = 3;
+
+// A position and its change since the previous trading day in percent
+#[derive(Debug, Serialize)]
+pub struct Mover {
+    pub name: String,
+    pub percent: f64,
+}
+
+// Key figures of the performance subcommand, changes that could not be
+// computed are None
+#[derive(Debug, Serialize)]
+pub struct PerformanceReport {
+    pub total: f64,
+    pub securities: f64,
+    pub cash: f64,
+    pub ytd_percent: Option<f64>,
+    pub month_percent: Option<f64>,
+    pub since_last_check_percent: Option<f64>,
+    pub benchmark: Option<Benchmark>,
+    pub top_gainers: Vec<Mover>,
+    pub top_losers: Vec<Mover>,
+}
+
+// Changes of the benchmark ticker over the same periods as the portfolio
+#[derive(Debug, Serialize)]
+pub struct Benchmark {
+    pub ticker: String,
+    pub ytd_percent: Option<f64>,
+    pub month_percent: Option<f64>,
+}
+
+// Trades that bring every balance to the same share of their sum,
+// positive values are buys and negative values are sells
+pub fn equal_weight_trades(balances: &[f64]) -> Vec<f64> {
+    let target = balances.iter().sum::<f64>() / balances.len() as f64;
+    balances.iter().map(|balance| target - balance).collect()
+}
 
 pub struct Portfolio {
     positions: Vec<PortfolioPosition>,
@@ -16,6 +241,21 @@ impl Portfolio {
         }
     }
 
+    // A copy with only the securities and non-cash assets, percentages
+    // computed on it are relative to the invested value
+    pub fn without_cash(&self) -> Portfolio {
+        Portfolio {
+            positions: self
+                .positions
+                .iter()
+                .filter(|p| {
+                    p.get_ticker().is_some() || !p.get_asset_class().eq_ignore_ascii_case("cash")
+                })
+                .cloned()
+                .collect(),
+        }
+    }
+
     pub fn add_position(&mut self, position: PortfolioPosition) {
         self.positions.push(position);
     }
@@ -29,129 +269,598 @@ impl Portfolio {
         sum
     }
 
-    // Get the total value of the portfolio at a specific date
-    // TODO: this function is not working as intended and the y_response is often an error
-    pub async fn get_historic_total_value(&self, date: DateTime<Utc>) -> Result<f64, String> {
+    // Get the total value of the portfolio in `currency` at a specific date
+    // with the prices and FX rates of that date, cash positions count with
+    // their current amount
+    pub async fn get_historic_total_value(
+        &self,
+        date: DateTime<Utc>,
+        source: &impl PriceSource,
+        currency: &str,
+    ) -> Result<f64, String> {
         let mut sum = 0.0;
 
         for position in &self.positions {
-            let y_response = get_historic_price(
-                {
-                    let this = &position;
-                    this.get_name()
-                },
-                date,
-            )
-            .await;
+            let error = |e: yahoo_finance_api::YahooError| {
+                format!(
+                    "Error getting historic price data for {}: {}",
+                    position.get_name(),
+                    e
+                )
+            };
+            let (mut value, value_currency) = match position.get_ticker() {
+                Some(ticker) => (
+                    source
+                        .get_historic_price(ticker, date)
+                        .await
+                        .map_err(error)?
+                        * position.get_amount(),
+                    position.get_quote_currency(),
+                ),
+                None => (position.get_amount(), position.get_currency()),
+            };
 
-            match y_response {
-                Ok(response) => match response.last_quote() {
-                    Ok(quote) => {
-                        sum += quote.close * position.get_amount();
-                    }
-                    Err(e) => {
-                        return Err(format!(
-                            "Error getting last quote for {}: {}",
-                            position.get_name(),
-                            e
-                        ));
-                    }
-                },
-                Err(e) => {
-                    return Err(format!(
-                        "Error getting historic price data for {}: {}",
-                        position.get_name(),
-                        e
-                    ));
+            if let Some(value_currency) = value_currency {
+                let (major, factor) = major_currency(value_currency);
+                value *= factor;
+                // an unconverted balance stays in its own currency on both sides
+                if position.get_unconverted_currency().is_none() {
+                    value *= get_fx_rate(major, currency, Some(date), source)
+                        .await
+                        .map_err(error)?;
                 }
             }
+            sum += value;
         }
         Ok(sum)
     }
 
-    pub fn get_allocation(&self) -> HashMap<String, f64> {
+    // Number of securities with a known market price
+    pub fn count_priced(&self) -> usize {
+        self.positions
+            .iter()
+            .filter(|p| p.get_market_price().is_some_and(|price| price > 0.0))
+            .count()
+    }
+
+    // Number of priced securities whose price is from an expired cache entry
+    pub fn count_stale(&self, stale_tickers: &HashSet<String>) -> usize {
+        self.positions
+            .iter()
+            .filter(|p| p.get_market_price().is_some_and(|price| price > 0.0))
+            .filter(|p| p.get_ticker().is_some_and(|t| stale_tickers.contains(t)))
+            .count()
+    }
+
+    // Projected dividends of all positions with a known dividend yield
+    pub fn total_annual_dividends(&self) -> f64 {
+        self.positions
+            .iter()
+            .filter_map(|position| position.annual_dividend_income())
+            .sum()
+    }
+
+    // Total value without the liabilities, the base of all percentages
+    pub fn get_gross_value(&self) -> f64 {
+        self.get_total_value() + self.get_margin_used()
+    }
+
+    // Sum of all negative balances (e.g. a margin loan held as negative cash)
+    // returned as a positive number
+    pub fn get_margin_used(&self) -> f64 {
+        let mut sum = 0.0;
+
+        for position in &self.positions {
+            let balance = position.get_balance();
+            if balance < 0.0 {
+                sum -= balance;
+            }
+        }
+        sum
+    }
+
+    // The allocation is computed over the positive balances only, so that
+    // borrowed money does not push the percentages below 0 or above 100
+    pub fn get_allocation(&self, group_cash_by_currency: bool) -> HashMap<String, f64> {
         let mut allocation: HashMap<String, f64> = HashMap::new();
+        let gross_value = self.get_gross_value();
+        if gross_value <= 0.0 {
+            return allocation;
+        }
 
         for position in &self.positions {
-            let asset_class = position.get_asset_class();
+            let asset_class = position.get_allocation_class(group_cash_by_currency);
             let balance = position.get_balance();
-            let total_value = self.get_total_value();
+            if balance < 0.0 {
+                continue;
+            }
 
-            let percentage = balance / total_value * 100.0;
+            let percentage = balance / gross_value * 100.0;
+            *allocation.entry(asset_class).or_insert(0.0) += percentage;
+        }
+        allocation
+    }
+
+    pub fn get_holdings(&self, currency: &str) -> Holdings {
+        let total_value = self.get_total_value();
+
+        let positions = self
+            .positions
+            .iter()
+            .map(|position| Holding {
+                name: position.get_name().to_string(),
+                ticker: position.get_ticker().map(|t| t.to_string()),
+                asset_class: position.get_asset_class().to_string(),
+                amount: position.get_amount(),
+                market_price: position.get_market_price(),
+                balance: position.get_balance(),
+                allocation_percent: self.get_weight(position),
+            })
+            .collect();
+
+        Holdings {
+            currency: currency.to_string(),
+            total_value,
+            liabilities: self.get_margin_used(),
+            positions,
+        }
+    }
 
-            if let Some(value) = allocation.get_mut(asset_class) {
-                *value += percentage;
+    // Daily variation of all positions with a known previous close,
+    // sorted from best to worst
+    pub fn get_daily_movers(&self) -> Vec<(&str, f64)> {
+        let mut movers: Vec<(&str, f64)> = self
+            .positions
+            .iter()
+            .filter_map(|position| {
+                position.get_daily_variation_percent().map(|percent| {
+                    (
+                        position.get_ticker().unwrap_or(position.get_name()),
+                        percent,
+                    )
+                })
+            })
+            .collect();
+        movers.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        movers
+    }
+
+    // Print a one line summary of today's best and worst position
+    pub fn print_daily_movers(&self) {
+        let movers = self.get_daily_movers();
+
+        let colored = |percent: f64| {
+            let s = format!("{}%", format_signed(percent));
+            if percent >= 0.0 {
+                s.green()
             } else {
-                allocation.insert(asset_class.to_string(), percentage);
+                s.red()
             }
+        };
+
+        match (movers.first(), movers.last()) {
+            (Some(best), Some(worst)) if movers.len() > 1 => println!(
+                "Today: {} {} best, {} {} worst",
+                best.0,
+                colored(best.1),
+                worst.0,
+                colored(worst.1)
+            ),
+            (Some(only), _) => println!("Today: {} {}", only.0, colored(only.1)),
+            _ => (),
         }
-        allocation
     }
 
-    // Print the portfolio as a table
-    // maybe replace this function with a library
-    pub fn print(&self, include_sum: bool) {
+    // One CSV row per position, cash positions leave price and daily change empty
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("Name,Class,Amount,Price,Value,%Day\n");
+
+        for position in &self.positions {
+            // securities without a price leave price and value empty, not 0
+            let unpriced = position
+                .get_market_price()
+                .is_some_and(|price| price <= 0.0);
+            let row = [
+                csv_field(position.get_name()),
+                csv_field(position.get_asset_class()),
+                position.get_amount().to_string(),
+                position
+                    .get_market_price()
+                    .filter(|_| !unpriced)
+                    .map(|price| price.to_string())
+                    .unwrap_or_default(),
+                if unpriced {
+                    String::new()
+                } else {
+                    format!("{:.2}", position.get_balance())
+                },
+                position
+                    .get_daily_variation_percent()
+                    .map(|change| format!("{:.2}", change))
+                    .unwrap_or_default(),
+            ];
+            csv.push_str(&row.join(","));
+            csv.push('\n');
+        }
+        csv
+    }
+
+    // (asset class, current %, target %, drift %) for every asset class in the
+    // portfolio or in `targets`, sorted by asset class
+    pub fn allocation_drift(&self, targets: &HashMap<String, f64>) -> Vec<(String, f64, f64, f64)> {
+        let allocation = self.get_allocation(false);
+
+        let mut classes: Vec<String> = allocation.keys().cloned().collect();
+        for class in targets.keys() {
+            if !classes.iter().any(|c| c.eq_ignore_ascii_case(class)) {
+                classes.push(class.to_string());
+            }
+        }
+        classes.sort();
+
+        let lookup = |map: &HashMap<String, f64>, class: &str| {
+            map.iter()
+                .find(|(c, _)| c.eq_ignore_ascii_case(class))
+                .map_or(0.0, |(_, percent)| *percent)
+        };
+        classes
+            .into_iter()
+            .map(|class| {
+                let current = lookup(&allocation, &class);
+                let target = lookup(targets, &class);
+                (class, current, target, current - target)
+            })
+            .collect()
+    }
+
+    // Print how much to buy or sell per asset class to reach `targets`
+    pub fn print_target_rebalance(&self, targets: &HashMap<String, f64>) {
+        let target_sum: f64 = targets.values().sum();
+        if (target_sum - 100.0).abs() > 0.01 {
+            info(format!(
+                "Warning: the targets add up to {}% instead of 100%",
+                format_number(target_sum)
+            ));
+        }
+        // the allocation is relative to the gross value, see get_allocation
+        let gross_value = self.get_gross_value();
+
         println!(
-            "{0: >26} | {1: >12} | {2: >10} | {3: >10}",
-            "Name", "Asset Class", "Amount", "Balance"
+            "{0: >12} | {1: >8} | {2: >8} | {3: >8} | {4: >10}",
+            "Asset Class", "Current", "Target", "Drift", "Trade"
         );
         println!("====================================================================");
-        for position in &self.positions {
+        for (class, current, target, drift) in self.allocation_drift(targets) {
+            let d = format!("{: >8}", format_signed(drift));
+            let d = if drift > 0.0 { d.red() } else { d.green() };
+            let trade = -drift / 100.0 * gross_value;
+            let t = format!("{: >10}", format_signed(trade));
+            let t = if trade >= 0.0 { t.green() } else { t.red() };
+            println!(
+                "{0: >12} | {1: >8} | {2: >8} | {3} | {4}",
+                class,
+                format_number(current),
+                format_number(target),
+                d,
+                t
+            );
+        }
+    }
+
+    // Print the trades needed to give every non-cash position the same
+    // share of the securities value
+    pub fn print_equal_weight_rebalance(&self, show_tickers: bool) {
+        let securities: Vec<&PortfolioPosition> = self
+            .positions
+            .iter()
+            .filter(|position| position.get_ticker().is_some())
+            .collect();
+        if securities.is_empty() {
+            println!("There are no securities to rebalance.");
+            return;
+        }
+
+        let balances: Vec<f64> = securities.iter().map(|p| p.get_balance()).collect();
+        let trades = equal_weight_trades(&balances);
+
+        println!(
+            "{0: >26} | {1: >10} | {2: >10} | {3: >10}",
+            "Name", "Balance", "Target", "Trade"
+        );
+        println!("====================================================================");
+        for ((position, balance), trade) in securities.iter().zip(&balances).zip(trades) {
+            let s = format!("{: >10}", format_signed(trade));
+            let s = if trade >= 0.0 { s.green() } else { s.red() };
             println!(
-                "{0: >26} | {1: >12} | {2: >10.2} | {3: >10.2}",
-                position.get_name(),
-                position.get_asset_class(),
-                position.get_amount(),
-                position.get_balance()
+                "{0: >26} | {1: >10} | {2: >10} | {3}",
+                position.get_display_name(show_tickers),
+                format_number(*balance),
+                format_number(balance + trade),
+                s
             );
         }
+    }
+
+    // Share of the gross value of a position in percent, liabilities are
+    // negative. Every position weighs 0% in a portfolio without value.
+    pub fn get_weight(&self, position: &PortfolioPosition) -> f64 {
+        let gross_value = self.get_gross_value();
+        if gross_value == 0.0 {
+            return 0.0;
+        }
+        position.get_balance() / gross_value * 100.0
+    }
+
+    // Percentage points a position is above (positive) or below (negative)
+    // its target weight, if it has one
+    pub fn get_weight_drift(&self, position: &PortfolioPosition) -> Option<f64> {
+        position
+            .get_target_weight()
+            .map(|target| self.get_weight(position) - target)
+    }
+
+    // Splits off the `top` most valuable positions, the rest is returned
+    // second. Without a limit all positions are kept in their original order
+    pub fn split_top(
+        &self,
+        top: Option<usize>,
+    ) -> (Vec<&PortfolioPosition>, Vec<&PortfolioPosition>) {
+        let mut positions: Vec<&PortfolioPosition> = self.positions.iter().collect();
+        let Some(top) = top else {
+            return (positions, Vec::new());
+        };
+        positions.sort_by(|a, b| b.get_balance().total_cmp(&a.get_balance()));
+        let rest = positions.split_off(top.min(positions.len()));
+        (positions, rest)
+    }
+
+    // Print the portfolio as a table without the disabled columns,
+    // weight columns are only shown if a position has a target weight
+    pub fn print(
+        &self,
+        include_sum: bool,
+        show_tickers: bool,
+        disabled: &DisabledComponents,
+        top: Option<usize>,
+    ) {
+        let show = |component| !disabled.is_disabled(component);
+        let show_weights = show(Component::Weight)
+            && self
+                .positions
+                .iter()
+                .any(|p| p.get_target_weight().is_some());
+        let show_dividends = show(Component::Dividend)
+            && self
+                .positions
+                .iter()
+                .any(|p| p.annual_dividend_income().is_some());
+
+        let mut header = vec![format!("{: >26}", "Name")];
+        if show(Component::AssetClass) {
+            header.push(format!("{: >12}", "Asset Class"));
+        }
+        if show(Component::Amount) {
+            header.push(format!("{: >10}", "Amount"));
+        }
+        if show(Component::Balance) {
+            header.push(format!("{: >10}", "Balance"));
+        }
+        if show_weights {
+            header.push(format!(
+                "{: >8} | {: >8} | {: >8}",
+                "Weight", "Target", "Drift"
+            ));
+        }
+        if show_dividends {
+            header.push(format!("{: >10}", "Dividends"));
+        }
+        println!("{}", header.join(" | "));
+        println!("====================================================================");
+
+        let (shown, rest) = self.split_top(top);
+        for position in shown {
+            let mut row = vec![format!("{: >26}", position.get_display_name(show_tickers))];
+            if show(Component::AssetClass) {
+                row.push(format!("{: >12}", position.get_asset_class()));
+            }
+            if show(Component::Amount) {
+                row.push(format!(
+                    "{: >10}",
+                    format_localized_amount(position.get_amount())
+                ));
+            }
+            if show(Component::Balance) {
+                match position.get_unconverted_currency() {
+                    // flag balances in a different currency
+                    Some(currency) => row.push(format!(
+                        "{}",
+                        format!("{: >6} {}", format_number(position.get_balance()), currency)
+                            .yellow()
+                    )),
+                    None => row.push(format!("{: >10}", format_number(position.get_balance()))),
+                }
+            }
+            if show_weights {
+                row.push(format!("{: >8}", format_number(self.get_weight(position))));
+                match (
+                    position.get_target_weight(),
+                    self.get_weight_drift(position),
+                ) {
+                    (Some(target), Some(drift)) => {
+                        let s = format!("{: >8}", format_signed(drift));
+                        // over target is flagged red, under target yellow
+                        let s = if drift > 0.0 {
+                            s.red()
+                        } else if drift < 0.0 {
+                            s.yellow()
+                        } else {
+                            s.normal()
+                        };
+                        row.push(format!("{: >8} | {}", format_number(target), s));
+                    }
+                    _ => row.push(format!("{: >8} | {: >8}", "-", "-")),
+                }
+            }
+            if show_dividends {
+                match position.annual_dividend_income() {
+                    Some(income) => row.push(format!("{: >10}", format_number(income))),
+                    None => row.push(format!("{: >10}", "-")),
+                }
+            }
+            println!("{}", row.join(" | "));
+        }
+        // the remaining positions are summed up in a single row
+        if !rest.is_empty() {
+            let mut row = vec![format!(
+                "{: >26}",
+                format!("Other ({} positions)", rest.len())
+            )];
+            if show(Component::AssetClass) {
+                row.push(format!("{: >12}", "-"));
+            }
+            if show(Component::Amount) {
+                row.push(format!("{: >10}", "-"));
+            }
+            if show(Component::Balance) {
+                let balance: f64 = rest.iter().map(|p| p.get_balance()).sum();
+                row.push(format!("{: >10}", format_number(balance)));
+            }
+            if show_weights {
+                let weight: f64 = rest.iter().map(|p| self.get_weight(p)).sum();
+                row.push(format!(
+                    "{: >8} | {: >8} | {: >8}",
+                    format_number(weight),
+                    "-",
+                    "-"
+                ));
+            }
+            if show_dividends {
+                let income: f64 = rest.iter().filter_map(|p| p.annual_dividend_income()).sum();
+                row.push(format!("{: >10}", format_number(income)));
+            }
+            println!("{}", row.join(" | "));
+        }
         if include_sum {
             println!("====================================================================");
-            println!("Your total balance is: {:.2}", self.get_total_value());
+            let liabilities = self.get_margin_used();
+            if liabilities > 0.0 {
+                println!("Liabilities: {}", format_number(liabilities));
+            }
+            println!(
+                "Your total balance is: {}",
+                format_number(self.get_total_value())
+            );
+            if show_dividends {
+                println!(
+                    "Projected annual dividends: {}",
+                    format_number(self.total_annual_dividends())
+                );
+            }
         }
     }
 
-    // Print the allocation in descending order %-wise
-    pub fn print_allocation(&self) {
-        let allocation = self.get_allocation();
-
-        // create a vector and sort it by the %-value of the allocation in descending order
-        let mut allocation_vec: Vec<(&String, &f64)> = allocation.iter().collect();
-        allocation_vec.sort_by(|a, b| b.1.partial_cmp(a.1).unwrap());
+    // Print the allocation in the configured order (descending %-wise by default)
+    // asset classes with a configured band are flagged when they drift outside of it
+    pub fn print_allocation(
+        &self,
+        sort: AllocationSort,
+        order: &[String],
+        bands: &HashMap<String, AllocationBand>,
+        group_cash_by_currency: bool,
+    ) {
+        let allocation = self.get_allocation(group_cash_by_currency);
+        let allocation_vec = sort_allocation(&allocation, sort, order);
 
         println!("====================================");
         for (asset_class, percentage) in allocation_vec {
-            println!("{0: >12} | {1: >10.2}", asset_class, percentage);
+            let band = bands
+                .iter()
+                .find(|(class, _)| class.eq_ignore_ascii_case(&asset_class))
+                .map(|(_, band)| band);
+
+            let marker = match band {
+                Some(band) => {
+                    let range = format!("{}-{}%", format_number(band.min), format_number(band.max));
+                    match band.drift(percentage) {
+                        Some(BandDrift::Above) => {
+                            format!("rebalance needed: above {}", range).red()
+                        }
+                        Some(BandDrift::Below) => {
+                            format!("rebalance needed: below {}", range).red()
+                        }
+                        None => format!("within {}", range).green(),
+                    }
+                }
+                None => "".normal(),
+            };
+
+            println!(
+                "{0: >12} | {1: >10} | {2}",
+                asset_class,
+                format_number(percentage),
+                marker
+            );
+        }
+
+        let margin_used = self.get_margin_used();
+        if margin_used > 0.0 {
+            println!("====================================");
+            println!(
+                "{0: >12} | {1: >10}",
+                "Liabilities",
+                format_number(margin_used)
+            );
         }
     }
 
-    pub fn draw_pie_chart(&self) {
-        let mut data = vec![];
+    // Slices of the pie chart, positions below `min_slice` percent of the
+    // total are combined into a single "Other" slice at the end
+    pub fn pie_slices(&self, show_tickers: bool, min_slice: f64) -> Vec<(String, f64)> {
+        // a negative slice cannot be drawn, margin is listed by print_allocation
+        let balances: Vec<(&str, f64)> = self
+            .positions
+            .iter()
+            .map(|p| (p.get_display_name(show_tickers), p.get_balance()))
+            .filter(|(_, balance)| *balance >= 0.0)
+            .collect();
+        let total: f64 = balances.iter().map(|(_, balance)| balance).sum();
 
-        let colors = [
-            Color::Red,
-            Color::Green,
-            Color::Blue,
-            Color::Yellow,
-            Color::Cyan,
-            Color::White,
-            Color::Purple,
-            Color::Black,
-        ];
+        let mut slices = Vec::new();
+        let mut other = 0.0;
+        let mut other_count = 0;
+        for (name, balance) in balances {
+            if total > 0.0 && balance / total * 100.0 < min_slice {
+                other += balance;
+                other_count += 1;
+            } else {
+                slices.push((name.to_string(), balance));
+            }
+        }
+        if other_count > 0 {
+            slices.push(("Other".to_string(), other));
+        }
+        slices
+    }
 
-        for (i, position) in self.positions.iter().enumerate() {
-            let name = {
-                let this = &position;
-                this.get_name()
-            };
-            let balance = position.get_balance() as f32;
+    pub fn draw_pie_chart(&self, show_tickers: bool, palette: PiePalette, min_slice: f64) {
+        let slices = self.pie_slices(show_tickers, min_slice);
+        let colors = palette.colors(slices.len());
 
-            data.push(piechart::Data {
-                label: name.to_string(),
-                value: balance,
-                color: Some(colors[i % colors.len()].into()),
+        let data: Vec<piechart::Data> = slices
+            .into_iter()
+            .zip(colors)
+            .map(|((label, value), color)| piechart::Data {
+                label,
+                value: value as f32,
+                color: Some(color.into()),
                 fill: '•',
-            });
+            })
+            .collect();
+
+        // e.g. when no prices could be fetched
+        if data.iter().map(|d| d.value).sum::<f32>() <= 0.0 {
+            info("Nothing to draw, the portfolio has no value");
+            return;
         }
 
         Chart::new()
@@ -161,9 +870,14 @@ impl Portfolio {
             .draw(&data);
     }
 
-    pub async fn print_performance(&self) {
-        let db = sled::open("database").unwrap();
-
+    // Without a database there is no last balance check to compare against
+    pub async fn performance_report(
+        &self,
+        db: Option<&sled::Db>,
+        source: &impl PriceSource,
+        currency: &str,
+        benchmark: &str,
+    ) -> PerformanceReport {
         // Yahoo first of the year is YYYY-01-03
         let first_of_the_year = Utc
             .with_ymd_and_hms(Utc::now().year(), 1, 1, 0, 0, 0)
@@ -172,54 +886,143 @@ impl Portfolio {
             .with_ymd_and_hms(Utc::now().year(), Utc::now().month(), 3, 0, 0, 0)
             .unwrap();
 
-        let value_at_beginning_of_year = self.get_historic_total_value(first_of_the_year).await;
-        if let Err(e) = value_at_beginning_of_year {
-            println!("Error getting value for beginning of year: {}", e);
-            return;
-        }
-
-        let value_at_beginning_of_month = self.get_historic_total_value(first_of_the_month).await;
-        if let Err(e) = value_at_beginning_of_month {
-            println!("Error getting value for beginning of month: {}", e);
-            return;
-        }
+        let total = self.get_total_value();
+        let last_balance: Option<f64> = match db.map(|db| db.iter().last()) {
+            Some(Some(Ok(last))) => String::from_utf8_lossy(&last.1).parse().ok(),
+            _ => None,
+        };
+        // change from an earlier total to the current one, a rise is positive,
+        // undefined for an earlier total of 0
+        let change = |value: f64| (value != 0.0).then(|| (total - value) / value * 100.0);
 
-        let last: f64 = match &db.iter().last() {
-            Some(Ok(last)) => {
-                let last = String::from_utf8_lossy(&last.1).parse();
-                if let Ok(last) = last {
-                    last
-                } else {
-                    0.0
-                }
+        let ytd_percent = match self
+            .get_historic_total_value(first_of_the_year, source, currency)
+            .await
+        {
+            Ok(value) => change(value),
+            Err(e) => {
+                info(format!("Error getting value for beginning of year: {}", e));
+                None
+            }
+        };
+        let month_percent = match self
+            .get_historic_total_value(first_of_the_month, source, currency)
+            .await
+        {
+            Ok(value) => change(value),
+            Err(e) => {
+                info(format!("Error getting value for beginning of month: {}", e));
+                None
             }
-            _ => 0.0,
         };
 
-        let values = [
-            value_at_beginning_of_year,
-            value_at_beginning_of_month,
-            Ok(self.get_total_value()),
-        ];
-
-        for (i, value) in values.iter().enumerate() {
-            let value = match value {
-                Ok(value) => *value,
-                Err(_) => continue,
+        // an empty benchmark ticker disables the comparison
+        let benchmark = if benchmark.is_empty() {
+            None
+        } else {
+            let current = match source.get_quote_prices(benchmark).await {
+                Ok(quotes) => quotes.closes.last().copied(),
+                Err(e) => {
+                    info(format!("Error getting price for {}: {}", benchmark, e));
+                    None
+                }
             };
-            let performance = (last - value) / value * 100.0;
-            let s = format!("{:.2}%", performance);
-            let s = if performance >= 0.0 {
-                s.green()
-            } else {
-                s.red()
+            let since = |date| async move {
+                let current = current?;
+                let value = source.get_historic_price(benchmark, date).await.ok()?;
+                (value != 0.0).then(|| (current - value) / value * 100.0)
             };
+            Some(Benchmark {
+                ticker: benchmark.to_string(),
+                ytd_percent: since(first_of_the_year).await,
+                month_percent: since(first_of_the_month).await,
+            })
+        };
+
+        let cash: f64 = self
+            .positions
+            .iter()
+            .filter(|p| p.get_ticker().is_none())
+            .map(|p| p.get_balance())
+            .sum();
+
+        let movers = self.get_daily_movers();
+        let mover = |(name, percent): &(&str, f64)| Mover {
+            name: name.to_string(),
+            percent: *percent,
+        };
 
-            match i {
-                0 => println!("YTD: {}", s),
-                1 => println!("Since beginning of month: {}", s),
-                2 => println!("Since last balance check: {}", s),
-                _ => (),
+        PerformanceReport {
+            total,
+            securities: total - cash,
+            cash,
+            ytd_percent,
+            month_percent,
+            since_last_check_percent: last_balance.and_then(change),
+            benchmark,
+            top_gainers: movers
+                .iter()
+                .filter(|m| m.1 > 0.0)
+                .take(TOP_MOVERS)
+                .map(mover)
+                .collect(),
+            top_losers: movers
+                .iter()
+                .rev()
+                .filter(|m| m.1 < 0.0)
+                .take(TOP_MOVERS)
+                .map(mover)
+                .collect(),
+        }
+    }
+
+    pub async fn print_performance(
+        &self,
+        db: Option<&sled::Db>,
+        source: &impl PriceSource,
+        currency: &str,
+        benchmark: &str,
+    ) {
+        let report = self
+            .performance_report(db, source, currency, benchmark)
+            .await;
+
+        // changes that could not be calculated are shown as "n/a"
+        let colored = |percent: Option<f64>| match percent {
+            Some(percent) if percent >= 0.0 => format!("{}%", format_number(percent)).green(),
+            Some(percent) => format!("{}%", format_number(percent)).red(),
+            None => "n/a".normal(),
+        };
+
+        // there is no benchmark change since the last balance check
+        let benchmark = report.benchmark.as_ref();
+        let mut lines = vec![
+            ("YTD", report.ytd_percent, benchmark.map(|b| b.ytd_percent)),
+            (
+                "Since beginning of month",
+                report.month_percent,
+                benchmark.map(|b| b.month_percent),
+            ),
+        ];
+        // without a database there are no stored balances to compare with
+        if db.is_some() {
+            lines.push((
+                "Since last balance check",
+                report.since_last_check_percent,
+                None,
+            ));
+        }
+        for (label, performance, benchmark_performance) in lines {
+            match (benchmark, benchmark_performance) {
+                (Some(benchmark), Some(benchmark_performance)) => println!(
+                    "{}: {} | {}: {} | Alpha: {}",
+                    label,
+                    colored(performance),
+                    benchmark.ticker,
+                    colored(benchmark_performance),
+                    colored(performance.zip(benchmark_performance).map(|(p, b)| p - b))
+                ),
+                _ => println!("{}: {}", label, colored(performance)),
             }
         }
     }
@@ -228,12 +1031,506 @@ impl Portfolio {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::position::apply_names;
+    use crate::position::from_string;
+    use crate::position::handle_position;
+    use crate::position::MockSource;
+
+    fn portfolio_from_str(data: &str) -> Portfolio {
+        let mut portfolio = Portfolio::new();
+        for position in from_string(data, "Stocks").unwrap() {
+            portfolio.add_position(position);
+        }
+        portfolio
+    }
+
+    #[test]
+    fn test_negative_cash() {
+        let portfolio = portfolio_from_str(
+            r#"[
+                {"Name": "Savings", "AssetClass": "Cash", "Amount": 1000},
+                {"Name": "Gold coins", "AssetClass": "Gold", "Amount": 1000},
+                {"Name": "Margin loan", "AssetClass": "Cash", "Amount": -200}
+            ]"#,
+        );
+
+        assert_eq!(portfolio.get_total_value(), 1800.0);
+        assert_eq!(portfolio.get_margin_used(), 200.0);
+
+        let allocation = portfolio.get_allocation(false);
+        assert_eq!(allocation.get("Cash"), Some(&50.0));
+        assert_eq!(allocation.get("Gold"), Some(&50.0));
+        assert_eq!(allocation.values().sum::<f64>(), 100.0);
+
+        // weights are shares of the gross value as well
+        let [savings, _, loan] = &portfolio.positions[..] else {
+            panic!("expected three positions");
+        };
+        assert_eq!(portfolio.get_weight(savings), 50.0);
+        assert_eq!(portfolio.get_weight(loan), -10.0);
+
+        let holdings = portfolio.get_holdings("EUR");
+        assert_eq!(holdings.total_value, 1800.0);
+        assert_eq!(holdings.liabilities, 200.0);
+        assert_eq!(holdings.positions[1].allocation_percent, 50.0);
+    }
+
+    #[test]
+    fn test_get_allocation_cash_by_currency() {
+        let portfolio = portfolio_from_str(
+            r#"[
+                {"Name": "Euro account", "AssetClass": "Cash", "Currency": "EUR", "Amount": 300},
+                {"Name": "Dollar account", "AssetClass": "Cash", "Currency": "USD", "Amount": 100},
+                {"Name": "Gold coins", "AssetClass": "Gold", "Currency": "EUR", "Amount": 600}
+            ]"#,
+        );
+
+        let allocation = portfolio.get_allocation(false);
+        assert_eq!(allocation.get("Cash"), Some(&40.0));
+
+        let allocation = portfolio.get_allocation(true);
+        assert_eq!(allocation.get("Cash"), None);
+        assert_eq!(allocation.get("Cash (EUR)"), Some(&30.0));
+        assert_eq!(allocation.get("Cash (USD)"), Some(&10.0));
+        assert_eq!(allocation.get("Gold"), Some(&60.0));
+    }
+
+    #[test]
+    fn test_get_holdings() {
+        let portfolio = portfolio_from_str(
+            r#"[
+                {"Name": "Savings", "AssetClass": "Cash", "Amount": 250},
+                {"Name": "Gold coins", "AssetClass": "Gold", "Amount": 750}
+            ]"#,
+        );
+
+        let holdings = portfolio.get_holdings("EUR");
+        assert_eq!(holdings.currency, "EUR");
+        assert_eq!(holdings.total_value, 1000.0);
+        assert_eq!(holdings.positions.len(), 2);
+        assert_eq!(holdings.positions[0].name, "Savings");
+        assert_eq!(holdings.positions[0].market_price, None);
+        assert_eq!(holdings.positions[0].allocation_percent, 25.0);
+
+        let json = serde_json::to_value(&holdings).unwrap();
+        assert_eq!(json["positions"][1]["balance"], 750.0);
+    }
+
+    #[test]
+    fn test_without_cash() {
+        let portfolio = portfolio_from_str(
+            r#"[
+                {"Name": "Savings", "AssetClass": "cash", "Amount": 600},
+                {"Name": "Gold coins", "AssetClass": "Gold", "Amount": 300},
+                {"Name": "Silver coins", "AssetClass": "Silver", "Amount": 100}
+            ]"#,
+        );
+
+        let securities = portfolio.without_cash();
+        assert_eq!(securities.get_total_value(), 400.0);
+        let allocation = securities.get_allocation(false);
+        assert_eq!(allocation.get("Gold"), Some(&75.0));
+        assert_eq!(allocation.get("cash"), None);
+        assert_eq!(portfolio.get_total_value(), 1000.0);
+
+        // nothing left to divide by
+        let portfolio = portfolio_from_str(r#"[{"AssetClass": "Cash", "Amount": 100}]"#);
+        assert!(portfolio.without_cash().get_allocation(false).is_empty());
+    }
+
+    #[test]
+    fn test_allocation_drift() {
+        let portfolio = portfolio_from_str(
+            r#"[
+                {"Name": "Savings", "AssetClass": "Cash", "Amount": 300},
+                {"Name": "Gold coins", "AssetClass": "Gold", "Amount": 700}
+            ]"#,
+        );
+        let targets = HashMap::from([
+            ("cash".to_string(), 20.0),
+            ("Gold".to_string(), 50.0),
+            ("Stocks".to_string(), 30.0),
+        ]);
+
+        assert_eq!(
+            portfolio.allocation_drift(&targets),
+            vec![
+                ("Cash".to_string(), 30.0, 20.0, 10.0),
+                ("Gold".to_string(), 70.0, 50.0, 20.0),
+                ("Stocks".to_string(), 0.0, 30.0, -30.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_to_csv() {
+        let portfolio = portfolio_from_str(
+            r#"[
+                {"Name": "Savings, \"daily\"", "AssetClass": "Cash", "Amount": 250},
+                {"Name": "Apple", "Ticker": "AAPL", "Amount": 0.5}
+            ]"#,
+        );
+
+        assert_eq!(
+            portfolio.to_csv(),
+            "Name,Class,Amount,Price,Value,%Day\n\
+             \"Savings, \"\"daily\"\"\",Cash,250,,250.00,\n\
+             Apple,Stocks,0.5,,,\n"
+        );
+    }
+
+    #[test]
+    fn test_split_top() {
+        let portfolio = portfolio_from_str(
+            r#"[
+                {"Name": "Wallet", "AssetClass": "Cash", "Amount": 15},
+                {"Name": "Savings", "AssetClass": "Cash", "Amount": 900},
+                {"Name": "Tip jar", "AssetClass": "Cash", "Amount": 80}
+            ]"#,
+        );
+        let names = |positions: Vec<&PortfolioPosition>| {
+            positions
+                .iter()
+                .map(|p| p.get_name().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let (shown, rest) = portfolio.split_top(Some(2));
+        assert_eq!(names(shown), vec!["Savings", "Tip jar"]);
+        assert_eq!(names(rest), vec!["Wallet"]);
+
+        let (shown, rest) = portfolio.split_top(None);
+        assert_eq!(names(shown), vec!["Wallet", "Savings", "Tip jar"]);
+        assert!(rest.is_empty());
+
+        assert_eq!(portfolio.split_top(Some(5)).0.len(), 3);
+    }
+
+    #[test]
+    fn test_pie_slices() {
+        let portfolio = portfolio_from_str(
+            r#"[
+                {"Name": "Savings", "AssetClass": "Cash", "Amount": 900},
+                {"Name": "Wallet", "AssetClass": "Cash", "Amount": 15},
+                {"Name": "Piggy bank", "AssetClass": "Cash", "Amount": 5},
+                {"Name": "Tip jar", "AssetClass": "Cash", "Amount": 80},
+                {"Name": "Margin", "AssetClass": "Cash", "Amount": -50}
+            ]"#,
+        );
+
+        assert_eq!(portfolio.pie_slices(false, 0.0).len(), 4);
+        assert_eq!(
+            portfolio.pie_slices(false, 2.0),
+            vec![
+                ("Savings".to_string(), 900.0),
+                ("Tip jar".to_string(), 80.0),
+                ("Other".to_string(), 20.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_names_from_config() {
+        let mut positions = from_string(
+            r#"[
+                {"Name": "ISHARES CORE MSCI WORLD", "Ticker": "EUNL.DE", "Amount": 1},
+                {"Ticker": "aapl", "Amount": 1},
+                {"Name": "Savings", "AssetClass": "Cash", "Amount": 250}
+            ]"#,
+            "Stocks",
+        )
+        .unwrap();
+        let names = HashMap::from([
+            ("EUNL.DE".to_string(), "MSCI World".to_string()),
+            ("AAPL".to_string(), "Apple".to_string()),
+        ]);
+        apply_names(&mut positions, &names);
+
+        let mut portfolio = Portfolio::new();
+        for position in positions {
+            portfolio.add_position(position);
+        }
+        let csv = portfolio.to_csv();
+        assert!(csv.contains("\nMSCI World,Stocks,1,"));
+        assert!(csv.contains("\nApple,Stocks,1,"));
+        assert!(csv.contains("\nSavings,Cash,250,"));
+    }
+
+    #[test]
+    fn test_get_daily_movers_without_prices() {
+        let portfolio =
+            portfolio_from_str(r#"[{"Name": "Savings", "AssetClass": "Cash", "Amount": 250}]"#);
+        assert!(portfolio.get_daily_movers().is_empty());
+    }
+
+    #[test]
+    fn test_get_weight_drift() {
+        let portfolio = portfolio_from_str(
+            r#"[
+                {"Name": "Savings", "AssetClass": "Cash", "Amount": 250, "TargetWeight": 20},
+                {"Name": "Gold coins", "AssetClass": "Gold", "Amount": 750}
+            ]"#,
+        );
+
+        let savings = &portfolio.positions[0];
+        let gold = &portfolio.positions[1];
+        assert_eq!(portfolio.get_weight(savings), 25.0);
+        assert_eq!(portfolio.get_weight_drift(savings), Some(5.0));
+        assert_eq!(portfolio.get_weight_drift(gold), None);
+    }
+
+    #[test]
+    fn test_get_weight_without_value() {
+        let portfolio = portfolio_from_str(
+            r#"[{"Name": "Savings", "AssetClass": "Cash", "Amount": 0, "TargetWeight": 20}]"#,
+        );
+
+        let savings = &portfolio.positions[0];
+        assert_eq!(portfolio.get_weight(savings), 0.0);
+        assert_eq!(portfolio.get_weight_drift(savings), Some(-20.0));
+    }
+
+    #[test]
+    fn test_allocation_without_margin() {
+        let portfolio = portfolio_from_str(
+            r#"[
+                {"Name": "Savings", "AssetClass": "Cash", "Amount": 250},
+                {"Name": "Gold coins", "AssetClass": "Gold", "Amount": 750}
+            ]"#,
+        );
+
+        assert_eq!(portfolio.get_margin_used(), 0.0);
+        let allocation = portfolio.get_allocation(false);
+        assert_eq!(allocation.get("Cash"), Some(&25.0));
+        assert_eq!(allocation.get("Gold"), Some(&75.0));
+    }
+
+    #[test]
+    fn test_allocation_band_drift() {
+        let band = AllocationBand {
+            min: 55.0,
+            max: 65.0,
+        };
+        assert_eq!(band.drift(60.0), None);
+        assert_eq!(band.drift(55.0), None);
+        assert_eq!(band.drift(70.0), Some(BandDrift::Above));
+        assert_eq!(band.drift(50.0), Some(BandDrift::Below));
+    }
+
+    #[test]
+    fn test_equal_weight_trades() {
+        assert_eq!(
+            equal_weight_trades(&[100.0, 300.0, 200.0]),
+            vec![100.0, -100.0, 0.0]
+        );
+        assert_eq!(equal_weight_trades(&[50.0]), vec![0.0]);
+    }
+
+    #[test]
+    fn test_pie_palette_colors() {
+        let colors = PiePalette::Basic.colors(10);
+        assert_eq!(colors.len(), 10);
+        assert_eq!(colors[0], Color::Red);
+        assert_eq!(colors[8], Color::Red);
+
+        let colors = PiePalette::Ansi256.colors(16);
+        for (i, color) in colors.iter().enumerate() {
+            assert!(!colors[..i].contains(color));
+        }
+
+        let colors = PiePalette::Truecolor.colors(24);
+        for (i, color) in colors.iter().enumerate() {
+            assert!(!colors[..i].contains(color));
+        }
+    }
+
+    #[test]
+    fn test_hsv_to_rgb() {
+        assert_eq!(hsv_to_rgb(0.0, 1.0, 1.0), (255, 0, 0));
+        assert_eq!(hsv_to_rgb(120.0, 1.0, 1.0), (0, 255, 0));
+        assert_eq!(hsv_to_rgb(240.0, 1.0, 1.0), (0, 0, 255));
+    }
+
+    #[test]
+    fn test_sort_allocation() {
+        let allocation = HashMap::from([
+            ("Cash".to_string(), 50.0),
+            ("Stocks".to_string(), 30.0),
+            ("Bonds".to_string(), 20.0),
+        ]);
+
+        let names = |sorted: Vec<(String, f64)>| -> Vec<String> {
+            sorted.into_iter().map(|(class, _)| class).collect()
+        };
+
+        assert_eq!(
+            names(sort_allocation(
+                &allocation,
+                AllocationSort::Percentage,
+                &[]
+            )),
+            ["Cash", "Stocks", "Bonds"]
+        );
+        assert_eq!(
+            names(sort_allocation(
+                &allocation,
+                AllocationSort::Alphabetical,
+                &[]
+            )),
+            ["Bonds", "Cash", "Stocks"]
+        );
+        let order = ["stocks".to_string(), "cash".to_string()];
+        assert_eq!(
+            names(sort_allocation(&allocation, AllocationSort::Custom, &order)),
+            ["Stocks", "Cash", "Bonds"]
+        );
+    }
 
     #[tokio::test]
     async fn test_get_historic_total_value() {
         let portfolio = Portfolio::new();
         let date = Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap();
-        let value = portfolio.get_historic_total_value(date).await;
+        let value = portfolio
+            .get_historic_total_value(date, &MockSource::default(), "EUR")
+            .await;
         assert_eq!(value, Ok(0.0));
+
+        let portfolio = portfolio_from_str(
+            r#"[{"Ticker": "AAPL", "Amount": 2}, {"Name": "Cash", "Amount": 50}]"#,
+        );
+        let source = MockSource {
+            historic_prices: HashMap::from([("AAPL".to_string(), 100.0)]),
+            ..Default::default()
+        };
+        let value = portfolio
+            .get_historic_total_value(date, &source, "EUR")
+            .await;
+        assert_eq!(value, Ok(250.0));
+
+        let value = portfolio
+            .get_historic_total_value(date, &MockSource::default(), "EUR")
+            .await;
+        assert!(value.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_performance_report() {
+        let portfolio = portfolio_from_str(
+            r#"[
+                {"Name": "Savings", "AssetClass": "Cash", "Amount": 100},
+                {"Name": "Apple", "Ticker": "AAPL", "Amount": 1}
+            ]"#,
+        );
+        let source = MockSource {
+            historic_prices: HashMap::from([("AAPL".to_string(), 0.0)]),
+            ..Default::default()
+        };
+
+        let report = portfolio.performance_report(None, &source, "EUR", "").await;
+        assert_eq!(report.total, 100.0);
+        assert_eq!(report.cash, 100.0);
+        assert_eq!(report.securities, 0.0);
+        assert_eq!(report.ytd_percent, Some(0.0));
+        assert_eq!(report.since_last_check_percent, None);
+        assert!(report.benchmark.is_none());
+
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        db.insert("2024-01-01 10:00:00", "80").unwrap();
+        let report = portfolio
+            .performance_report(Some(&db), &source, "EUR", "")
+            .await;
+        assert_eq!(report.since_last_check_percent, Some(25.0));
+
+        // a drop since the last check is negative
+        db.insert("2024-01-02 10:00:00", "125").unwrap();
+        let report = portfolio
+            .performance_report(Some(&db), &source, "EUR", "")
+            .await;
+        assert_eq!(report.since_last_check_percent, Some(-20.0));
+
+        // there is no change from a total of 0
+        db.insert("2024-01-03 10:00:00", "0").unwrap();
+        let report = portfolio
+            .performance_report(Some(&db), &source, "EUR", "")
+            .await;
+        assert_eq!(report.since_last_check_percent, None);
+
+        // a failed price lookup serializes as null
+        let report = portfolio
+            .performance_report(None, &MockSource::default(), "EUR", "")
+            .await;
+        let json = serde_json::to_value(&report).unwrap();
+        assert!(json["ytd_percent"].is_null());
+        assert_eq!(json["total"], 100.0);
+    }
+
+    #[tokio::test]
+    async fn test_performance_report_converts_historic_prices() {
+        let source = MockSource {
+            prices: HashMap::from([
+                ("MSFT".to_string(), vec![110.0]),
+                ("VOD.L".to_string(), vec![1000.0]),
+                ("USDEUR=X".to_string(), vec![0.5]),
+                ("GBPEUR=X".to_string(), vec![1.25]),
+            ]),
+            currencies: HashMap::from([
+                ("MSFT".to_string(), "USD".to_string()),
+                ("VOD.L".to_string(), "GBp".to_string()),
+            ]),
+            historic_prices: HashMap::from([
+                ("MSFT".to_string(), 100.0),
+                ("VOD.L".to_string(), 800.0),
+                ("USDEUR=X".to_string(), 0.4),
+                ("GBPEUR=X".to_string(), 1.25),
+            ]),
+            ..Default::default()
+        };
+        let mut portfolio = Portfolio::new();
+        for mut position in from_string(
+            r#"[
+                {"Name": "Microsoft", "Ticker": "MSFT", "Amount": 1},
+                {"Name": "Vodafone", "Ticker": "VOD.L", "Amount": 10},
+                {"Name": "Dollar account", "AssetClass": "Cash", "Currency": "USD", "Amount": 100}
+            ]"#,
+            "Stocks",
+        )
+        .unwrap()
+        {
+            let position = handle_position(&mut position, &source, "EUR", false)
+                .await
+                .unwrap();
+            portfolio.add_position(position);
+        }
+
+        // today 55 + 125 + 50 EUR, at the beginning of the year 40 + 100 + 40
+        // EUR with the dollar at 0.4 instead of 0.5
+        assert_eq!(portfolio.get_total_value(), 230.0);
+        let report = portfolio.performance_report(None, &source, "EUR", "").await;
+        assert!((report.ytd_percent.unwrap() - 50.0 / 180.0 * 100.0).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_performance_report_benchmark() {
+        let portfolio =
+            portfolio_from_str(r#"[{"Name": "Savings", "AssetClass": "Cash", "Amount": 100}]"#);
+        let source = MockSource {
+            prices: HashMap::from([("^GSPC".to_string(), vec![110.0])]),
+            historic_prices: HashMap::from([("^GSPC".to_string(), 100.0)]),
+            ..Default::default()
+        };
+
+        let report = portfolio
+            .performance_report(None, &source, "EUR", "^GSPC")
+            .await;
+        let benchmark = report.benchmark.unwrap();
+        assert_eq!(benchmark.ticker, "^GSPC");
+        assert_eq!(benchmark.ytd_percent, Some(10.0));
+        assert_eq!(benchmark.month_percent, Some(10.0));
+
+        // an unknown benchmark has no changes
+        let report = portfolio
+            .performance_report(None, &MockSource::default(), "EUR", "^GSPC")
+            .await;
+        assert_eq!(report.benchmark.unwrap().ytd_percent, None);
     }
 }
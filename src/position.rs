@@ -1,18 +1,44 @@
+use crate::output::info;
 use chrono::prelude::*;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use time::OffsetDateTime;
 use yahoo_finance_api as yahoo;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Clone, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct PortfolioPosition {
     name: Option<String>,
     ticker: Option<String>,
+    #[serde(default)]
     asset_class: String,
     amount: f64,
+    // desired share of the total portfolio value in percent
+    target_weight: Option<f64>,
+    // currency of a cash position, e.g. "USD"
+    currency: Option<String>,
+    // annual dividends in percent of the price, fetched from Yahoo if not given
+    dividend_yield: Option<f64>,
 
     #[serde(skip_deserializing)]
     last_spot: f64,
+    #[serde(skip_deserializing)]
+    previous_close: Option<f64>,
+    // quote currency of a price that could not be converted
+    #[serde(skip_deserializing)]
+    unconverted_currency: Option<String>,
+    // currency Yahoo Finance quotes the ticker in, e.g. "USD" or "GBp"
+    #[serde(skip_deserializing)]
+    quote_currency: Option<String>,
+    // rate from `currency` into the configured currency for positions
+    // without a ticker
+    #[serde(skip_deserializing)]
+    fx_rate: Option<f64>,
 }
 
 impl PortfolioPosition {
@@ -30,86 +56,729 @@ impl PortfolioPosition {
         }
     }
 
+    // the ticker is shown instead of the name if requested and available
+    pub fn get_display_name(&self, show_ticker: bool) -> &str {
+        match &self.ticker {
+            Some(ticker) if show_ticker => ticker,
+            _ => self.get_name(),
+        }
+    }
+
+    pub fn get_ticker(&self) -> Option<&str> {
+        self.ticker.as_deref()
+    }
+
+    // cash positions have no market price
+    pub fn get_market_price(&self) -> Option<f64> {
+        self.ticker.as_ref().map(|_| self.last_spot)
+    }
+
     pub fn get_asset_class(&self) -> &str {
         &self.asset_class
     }
 
+    // cash is split up by currency if requested, e.g. "Cash (USD)"
+    pub fn get_allocation_class(&self, group_cash_by_currency: bool) -> String {
+        match &self.currency {
+            Some(currency)
+                if group_cash_by_currency && self.asset_class.eq_ignore_ascii_case("cash") =>
+            {
+                format!("{} ({})", self.asset_class, currency)
+            }
+            _ => self.asset_class.to_string(),
+        }
+    }
+
     pub fn get_balance(&self) -> f64 {
         if let Some(_ticker) = &self.ticker {
             self.last_spot * self.amount
         } else {
-            self.amount
+            self.amount * self.fx_rate.unwrap_or(1.0)
         }
     }
 
     pub fn get_amount(&self) -> f64 {
         self.amount
     }
+
+    pub fn get_target_weight(&self) -> Option<f64> {
+        self.target_weight
+    }
+
+    // dividend yield applied to the market value, cash pays no dividends
+    pub fn annual_dividend_income(&self) -> Option<f64> {
+        self.ticker.as_ref()?;
+        self.dividend_yield
+            .map(|dividend_yield| dividend_yield / 100.0 * self.get_balance())
+    }
+
+    // set if the price is still in the quote currency because no FX rate was found
+    pub fn get_unconverted_currency(&self) -> Option<&str> {
+        self.unconverted_currency.as_deref()
+    }
+
+    pub fn get_quote_currency(&self) -> Option<&str> {
+        self.quote_currency.as_deref()
+    }
+
+    pub fn get_currency(&self) -> Option<&str> {
+        self.currency.as_deref()
+    }
+
+    // change of the price since the previous trading day's close in percent
+    pub fn get_daily_variation_percent(&self) -> Option<f64> {
+        match self.previous_close {
+            Some(previous_close) if previous_close > 0.0 => {
+                Some((self.last_spot - previous_close) / previous_close * 100.0)
+            }
+            _ => None,
+        }
+    }
 }
 
-pub fn from_string(data: &str) -> Vec<PortfolioPosition> {
-    serde_json::from_str::<Vec<PortfolioPosition>>(data).expect("JSON was not well-formatted")
+// positions without an AssetClass get `default_asset_class`
+pub fn from_string(
+    data: &str,
+    default_asset_class: &str,
+) -> Result<Vec<PortfolioPosition>, String> {
+    let mut positions = serde_json::from_str::<Vec<PortfolioPosition>>(data)
+        .map_err(|e| format!("JSON was not well-formatted: {}", e))?;
+
+    let mut defaulted = Vec::new();
+    for position in &mut positions {
+        if position.asset_class.is_empty() {
+            defaulted.push(position.get_name().to_string());
+            position.asset_class = default_asset_class.to_string();
+        }
+    }
+    // one notice for all positions instead of one line each
+    if !defaulted.is_empty() {
+        info(format!(
+            "No AssetClass given for {}, using \"{}\"",
+            defaulted.join(", "),
+            default_asset_class
+        ));
+    }
+    Ok(positions)
 }
 
-// Get the latest price for a ticker
-async fn get_quote_price(ticker: &str) -> Result<yahoo::YResponse, yahoo::YahooError> {
-    yahoo::YahooConnector::new()?
-        .get_latest_quotes(ticker, "1d")
-        .await
+// Combine positions with the same ticker (e.g. from different broker files)
+// into the first one, positions without a ticker are kept as they are
+pub fn merge_duplicates(positions: Vec<PortfolioPosition>) -> Vec<PortfolioPosition> {
+    let mut merged: Vec<PortfolioPosition> = Vec::new();
+
+    for position in positions {
+        let existing = position.ticker.as_ref().and_then(|ticker| {
+            merged.iter_mut().find(|p| {
+                p.ticker
+                    .as_ref()
+                    .is_some_and(|t| t.eq_ignore_ascii_case(ticker))
+            })
+        });
+        match existing {
+            Some(existing) => existing.amount += position.amount,
+            None => merged.push(position),
+        }
+    }
+    merged
 }
 
-// get the price at a given date
-pub async fn get_historic_price(
-    ticker: &str,
-    date: DateTime<Utc>,
-) -> Result<yahoo::YResponse, yahoo::YahooError> {
-    let start = OffsetDateTime::from_unix_timestamp(date.timestamp()).unwrap();
+// Display names from the config win over the Name in the JSON and the name
+// from Yahoo Finance, the latter is then not fetched at all
+pub fn apply_names(positions: &mut [PortfolioPosition], names: &HashMap<String, String>) {
+    for position in positions {
+        let alias = position.ticker.as_ref().and_then(|ticker| {
+            names
+                .iter()
+                .find(|(t, _)| t.eq_ignore_ascii_case(ticker))
+                .map(|(_, name)| name)
+        });
+        if let Some(alias) = alias {
+            position.name = Some(alias.to_string());
+        }
+    }
+}
 
-    // get a range of 3 days in case the market is closed on the given date
-    let end = start + time::Duration::days(3);
+// Structural mistakes in the portfolio file, positions are still loaded
+#[derive(Debug, PartialEq)]
+pub enum ValidationError {
+    // neither Name nor Ticker, the position shows up as "Unknown"
+    Unnamed { index: usize },
+    EmptyTicker { name: String },
+    // whitespace or characters Yahoo Finance does not use in symbols
+    MalformedTicker { name: String, ticker: String },
+    // a missing AssetClass falls back to the default, a blank one does not
+    BlankAssetClass { name: String },
+    // negative cash is fine (margin), securities need a positive Amount
+    NonPositiveAmount { name: String, amount: f64 },
+    // without a ticker only cash has a known value
+    UnpricedAsset { name: String, asset_class: String },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValidationError::Unnamed { index } => {
+                write!(f, "Position #{} has neither a Name nor a Ticker", index + 1)
+            }
+            ValidationError::EmptyTicker { name } => write!(f, "{} has an empty Ticker", name),
+            ValidationError::MalformedTicker { name, ticker } => {
+                write!(f, "{} has a malformed Ticker \"{}\"", name, ticker)
+            }
+            ValidationError::BlankAssetClass { name } => {
+                write!(f, "{} has a blank AssetClass", name)
+            }
+            ValidationError::NonPositiveAmount { name, amount } => {
+                write!(f, "{} has a non-positive Amount ({})", name, amount)
+            }
+            ValidationError::UnpricedAsset { name, asset_class } => write!(
+                f,
+                "{} has no Ticker but is not Cash ({}), its Amount is used as its value",
+                name, asset_class
+            ),
+        }
+    }
+}
+
+pub fn validate_positions(positions: &[PortfolioPosition]) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+
+    for (index, position) in positions.iter().enumerate() {
+        let name = position.get_name().to_string();
+        match &position.ticker {
+            None if position.name.is_none() => errors.push(ValidationError::Unnamed { index }),
+            None if !position.asset_class.eq_ignore_ascii_case("cash") => {
+                errors.push(ValidationError::UnpricedAsset {
+                    name: name.to_string(),
+                    asset_class: position.asset_class.to_string(),
+                })
+            }
+            Some(ticker) if ticker.trim().is_empty() => errors.push(ValidationError::EmptyTicker {
+                name: name.to_string(),
+            }),
+            Some(ticker) if !is_valid_ticker(ticker) => {
+                errors.push(ValidationError::MalformedTicker {
+                    name: name.to_string(),
+                    ticker: ticker.to_string(),
+                })
+            }
+            Some(_) if position.amount <= 0.0 => errors.push(ValidationError::NonPositiveAmount {
+                name: name.to_string(),
+                amount: position.amount,
+            }),
+            _ => (),
+        }
+        if !position.asset_class.is_empty() && position.asset_class.trim().is_empty() {
+            errors.push(ValidationError::BlankAssetClass { name });
+        }
+    }
+    errors
+}
 
-    yahoo::YahooConnector::new()?
-        .get_quote_history(ticker, start, end)
+// Yahoo Finance symbols look like AAPL, BRK-B, VOW3.DE, ^GSPC or EURUSD=X
+fn is_valid_ticker(ticker: &str) -> bool {
+    ticker
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '^' | '='))
+}
+
+// Problems in a portfolio file, checked without fetching any prices
+pub fn validate_portfolio_json(data: &str) -> Vec<String> {
+    match serde_json::from_str::<Vec<PortfolioPosition>>(data) {
+        Ok(positions) => validate_positions(&positions)
+            .iter()
+            .map(|e| e.to_string())
+            .collect(),
+        Err(e) => vec![format!("Not a valid portfolio: {}", e)],
+    }
+}
+
+// Daily closes of the last month, oldest first, in the quote currency
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Quotes {
+    pub closes: Vec<f64>,
+    pub currency: Option<String>,
+}
+
+// Where quotes come from, Yahoo Finance for live runs and a fixed set of
+// prices in tests
+pub trait PriceSource {
+    fn get_quote_prices(
+        &self,
+        ticker: &str,
+    ) -> impl Future<Output = Result<Quotes, yahoo::YahooError>> + Send;
+
+    // The close at a given date
+    fn get_historic_price(
+        &self,
+        ticker: &str,
+        date: DateTime<Utc>,
+    ) -> impl Future<Output = Result<f64, yahoo::YahooError>> + Send;
+
+    // Dividends paid per share over the last year, in the quote currency
+    fn get_annual_dividend(
+        &self,
+        ticker: &str,
+    ) -> impl Future<Output = Result<f64, yahoo::YahooError>> + Send;
+
+    // The short name for a ticker
+    fn get_quote_name(
+        &self,
+        ticker: &str,
+    ) -> impl Future<Output = Result<String, yahoo::YahooError>> + Send;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct YahooSource;
+
+impl PriceSource for YahooSource {
+    async fn get_quote_prices(&self, ticker: &str) -> Result<Quotes, yahoo::YahooError> {
+        let response = yahoo::YahooConnector::new()?
+            .get_latest_quotes(ticker, "1d")
+            .await?;
+        // an empty response leaves the position without a price
+        let quotes = response.quotes().unwrap_or_default();
+        Ok(Quotes {
+            closes: quotes.iter().map(|quote| quote.close).collect(),
+            currency: response.metadata().ok().and_then(|meta| meta.currency),
+        })
+    }
+
+    async fn get_historic_price(
+        &self,
+        ticker: &str,
+        date: DateTime<Utc>,
+    ) -> Result<f64, yahoo::YahooError> {
+        let start = OffsetDateTime::from_unix_timestamp(date.timestamp()).unwrap();
+
+        // get a range of 3 days in case the market is closed on the given date
+        let end = start + time::Duration::days(3);
+
+        let quote = yahoo::YahooConnector::new()?
+            .get_quote_history(ticker, start, end)
+            .await?
+            .last_quote()?;
+        Ok(quote.close)
+    }
+
+    async fn get_annual_dividend(&self, ticker: &str) -> Result<f64, yahoo::YahooError> {
+        let dividends = yahoo::YahooConnector::new()?
+            .get_quote_range(ticker, "1d", "1y")
+            .await?
+            .dividends()?;
+        Ok(dividends.iter().map(|dividend| dividend.amount).sum())
+    }
+
+    async fn get_quote_name(&self, ticker: &str) -> Result<String, yahoo::YahooError> {
+        let connector = yahoo::YahooConnector::new();
+        let resp = connector?.search_ticker(ticker).await?;
+
+        if let Some(item) = resp.quotes.first() {
+            Ok(item.short_name.clone())
+        } else {
+            Err(yahoo::YahooError::EmptyDataSet)
+        }
+    }
+}
+
+// Last known quotes, dividends and name of a ticker, as stored in the quote cache
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct CachedQuote {
+    closes: Vec<f64>,
+    currency: Option<String>,
+    name: Option<String>,
+    // unix timestamp of the last successful fetch
+    updated: i64,
+    #[serde(default)]
+    dividend: Option<f64>,
+    // unix timestamp of the last successful dividend fetch
+    #[serde(default)]
+    dividend_updated: i64,
+}
+
+// retries of a failed fetch, the delay doubles after each one (200ms, 400ms, ...)
+const FETCH_RETRIES: u32 = 3;
+const FETCH_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+// Rate limits, server errors and dropped connections may pass,
+// an unknown ticker (404) will not
+fn is_transient(e: &yahoo::YahooError) -> bool {
+    match e {
+        yahoo::YahooError::ConnectionFailed(_) => true,
+        yahoo::YahooError::FetchFailed(status) => {
+            status.starts_with("429") || status.starts_with('5')
+        }
+        _ => false,
+    }
+}
+
+// Runs `fetch` until it succeeds, fails for good or `retries` retries are used up
+async fn with_retries<T, F, Fut>(
+    retries: u32,
+    delay: Duration,
+    mut fetch: F,
+) -> Result<T, yahoo::YahooError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, yahoo::YahooError>>,
+{
+    let mut delay = delay;
+    for _ in 0..retries {
+        match fetch().await {
+            Err(e) if is_transient(&e) => {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+    fetch().await
+}
+
+// Serves quotes younger than `ttl` seconds from a file, older quotes are
+// refreshed and only used if the refresh fails (e.g. when offline)
+#[derive(Debug, Clone)]
+pub struct CachedSource<S> {
+    inner: S,
+    path: Option<PathBuf>,
+    ttl: i64,
+    // never ask the inner source, unknown tickers have no price
+    offline: bool,
+    quotes: Arc<Mutex<HashMap<String, CachedQuote>>>,
+    // tickers served from expired quotes because the inner source was not
+    // asked or failed
+    stale: Arc<Mutex<HashSet<String>>>,
+}
+
+impl<S> CachedSource<S> {
+    // a missing or unreadable cache file starts an empty cache
+    pub fn load(inner: S, path: Option<PathBuf>, ttl: u64) -> Self {
+        let quotes = path
+            .as_ref()
+            .and_then(|path| std::fs::read(path).ok())
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+
+        CachedSource {
+            inner,
+            path,
+            ttl: ttl as i64,
+            offline: false,
+            quotes: Arc::new(Mutex::new(quotes)),
+            stale: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    // includes FX pairs, e.g. "USDEUR=X"
+    pub fn stale_tickers(&self) -> HashSet<String> {
+        self.stale.lock().unwrap().clone()
+    }
+
+    pub fn offline(self) -> Self {
+        CachedSource {
+            offline: true,
+            ..self
+        }
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let data = serde_json::to_vec(&*self.quotes.lock().unwrap()).unwrap();
+        std::fs::write(path, data)
+    }
+}
+
+impl<S: PriceSource + Sync> PriceSource for CachedSource<S> {
+    async fn get_quote_prices(&self, ticker: &str) -> Result<Quotes, yahoo::YahooError> {
+        let now = Utc::now().timestamp();
+        let cached = self.quotes.lock().unwrap().get(ticker).cloned();
+        let cached = cached.filter(|quote| !quote.closes.is_empty());
+        let to_quotes = |quote: CachedQuote| Quotes {
+            closes: quote.closes,
+            currency: quote.currency,
+        };
+
+        if let Some(quote) = &cached {
+            if now - quote.updated < self.ttl {
+                return Ok(to_quotes(quote.clone()));
+            }
+            if self.offline {
+                self.stale.lock().unwrap().insert(ticker.to_string());
+                return Ok(to_quotes(quote.clone()));
+            }
+        }
+        if self.offline {
+            return Ok(Quotes::default());
+        }
+
+        let fetched = with_retries(FETCH_RETRIES, FETCH_RETRY_DELAY, || {
+            self.inner.get_quote_prices(ticker)
+        })
+        .await;
+        match fetched {
+            Ok(quotes) => {
+                let mut cache = self.quotes.lock().unwrap();
+                let entry = cache.entry(ticker.to_string()).or_default();
+                entry.closes.clone_from(&quotes.closes);
+                entry.currency.clone_from(&quotes.currency);
+                entry.updated = now;
+                Ok(quotes)
+            }
+            Err(e) => match cached {
+                Some(quote) => {
+                    self.stale.lock().unwrap().insert(ticker.to_string());
+                    info(format!(
+                        "Could not update {}, using the price from {}",
+                        ticker,
+                        DateTime::from_timestamp(quote.updated, 0)
+                            .unwrap_or_default()
+                            .with_timezone(&Local)
+                            .format("%Y-%m-%d %H:%M")
+                    ));
+                    Ok(to_quotes(quote))
+                }
+                None => Err(e),
+            },
+        }
+    }
+
+    async fn get_historic_price(
+        &self,
+        ticker: &str,
+        date: DateTime<Utc>,
+    ) -> Result<f64, yahoo::YahooError> {
+        if self.offline {
+            return Err(yahoo::YahooError::EmptyDataSet);
+        }
+        with_retries(FETCH_RETRIES, FETCH_RETRY_DELAY, || {
+            self.inner.get_historic_price(ticker, date)
+        })
         .await
+    }
+
+    // cached like quotes, expired dividends are used offline or if the
+    // inner source fails
+    async fn get_annual_dividend(&self, ticker: &str) -> Result<f64, yahoo::YahooError> {
+        let now = Utc::now().timestamp();
+        let cached = self
+            .quotes
+            .lock()
+            .unwrap()
+            .get(ticker)
+            .and_then(|quote| quote.dividend.map(|d| (d, quote.dividend_updated)));
+
+        if let Some((dividend, updated)) = cached {
+            if self.offline || now - updated < self.ttl {
+                return Ok(dividend);
+            }
+        }
+        if self.offline {
+            return Err(yahoo::YahooError::EmptyDataSet);
+        }
+
+        let fetched = with_retries(FETCH_RETRIES, FETCH_RETRY_DELAY, || {
+            self.inner.get_annual_dividend(ticker)
+        })
+        .await;
+        match fetched {
+            Ok(dividend) => {
+                let mut cache = self.quotes.lock().unwrap();
+                let entry = cache.entry(ticker.to_string()).or_default();
+                entry.dividend = Some(dividend);
+                entry.dividend_updated = now;
+                Ok(dividend)
+            }
+            Err(e) => cached.map(|(dividend, _)| dividend).ok_or(e),
+        }
+    }
+
+    // names hardly ever change, a cached name is always used
+    async fn get_quote_name(&self, ticker: &str) -> Result<String, yahoo::YahooError> {
+        let cached = self
+            .quotes
+            .lock()
+            .unwrap()
+            .get(ticker)
+            .and_then(|quote| quote.name.clone());
+        if let Some(name) = cached {
+            return Ok(name);
+        }
+        if self.offline {
+            return Ok(ticker.to_string());
+        }
+
+        let name = self.inner.get_quote_name(ticker).await?;
+        self.quotes
+            .lock()
+            .unwrap()
+            .entry(ticker.to_string())
+            .or_default()
+            .name = Some(name.clone());
+        Ok(name)
+    }
+}
+
+// Fixed prices for offline tests, tickers without a price are an error
+#[cfg(test)]
+#[derive(Debug, Default, Clone)]
+pub struct MockSource {
+    pub prices: HashMap<String, Vec<f64>>,
+    pub currencies: HashMap<String, String>,
+    pub historic_prices: HashMap<String, f64>,
+    pub dividends: HashMap<String, f64>,
+    pub names: HashMap<String, String>,
+}
+
+#[cfg(test)]
+impl PriceSource for MockSource {
+    async fn get_quote_prices(&self, ticker: &str) -> Result<Quotes, yahoo::YahooError> {
+        let closes = self
+            .prices
+            .get(ticker)
+            .cloned()
+            .ok_or(yahoo::YahooError::EmptyDataSet)?;
+        Ok(Quotes {
+            closes,
+            currency: self.currencies.get(ticker).cloned(),
+        })
+    }
+
+    async fn get_historic_price(
+        &self,
+        ticker: &str,
+        _date: DateTime<Utc>,
+    ) -> Result<f64, yahoo::YahooError> {
+        self.historic_prices
+            .get(ticker)
+            .copied()
+            .ok_or(yahoo::YahooError::EmptyDataSet)
+    }
+
+    async fn get_annual_dividend(&self, ticker: &str) -> Result<f64, yahoo::YahooError> {
+        self.dividends
+            .get(ticker)
+            .copied()
+            .ok_or(yahoo::YahooError::EmptyDataSet)
+    }
+
+    async fn get_quote_name(&self, ticker: &str) -> Result<String, yahoo::YahooError> {
+        self.names
+            .get(ticker)
+            .cloned()
+            .ok_or(yahoo::YahooError::EmptyDataSet)
+    }
 }
 
-// Try to get the short name for a ticker from Yahoo Finance
-async fn get_quote_name(ticker: &str) -> Result<String, yahoo::YahooError> {
-    let connector = yahoo::YahooConnector::new();
-    let resp = connector?.search_ticker(ticker).await?;
+// London quotes are in pence, returns the currency a quote currency
+// belongs to and the factor to get there
+pub fn major_currency(quote_currency: &str) -> (&str, f64) {
+    match quote_currency {
+        "GBp" => ("GBP", 0.01),
+        currency => (currency, 1.0),
+    }
+}
 
-    if let Some(item) = resp.quotes.first() {
-        Ok(item.short_name.clone())
-    } else {
-        Err(yahoo::YahooError::EmptyDataSet)
+// Rate to convert an amount in `from` into `to`, the latest one or the one
+// at `date`. Rates are quotes like any other, cached by a CachedSource
+pub async fn get_fx_rate(
+    from: &str,
+    to: &str,
+    date: Option<DateTime<Utc>>,
+    source: &impl PriceSource,
+) -> Result<f64, yahoo::YahooError> {
+    if from.eq_ignore_ascii_case(to) {
+        return Ok(1.0);
+    }
+
+    let pair = format!("{}{}=X", from.to_uppercase(), to.to_uppercase());
+    match date {
+        Some(date) => source.get_historic_price(&pair, date).await,
+        None => source
+            .get_quote_prices(&pair)
+            .await?
+            .closes
+            .last()
+            .copied()
+            .ok_or(yahoo::YahooError::EmptyDataSet),
     }
 }
 
-// Get the latest price for a ticker and update the positionthen
-// then return the updated position as a new object
+// Get the latest price for a ticker in `currency` and update the position
+// then return the updated position as a new object. Dividends are only
+// fetched if requested, they need a year of history per ticker
 pub async fn handle_position(
     position: &mut PortfolioPosition,
+    source: &impl PriceSource,
+    currency: &str,
+    fetch_dividends: bool,
 ) -> Result<PortfolioPosition, yahoo::YahooError> {
     if let Some(ticker) = &position.ticker {
-        let quote = get_quote_price(ticker).await?;
-        if let Ok(last_spot) = quote.last_quote() {
-            position.update_price(last_spot.close)
-        } else {
-            // if the market is closed, try to get the last available price
-            if let Ok(last_spot) = quote.quotes() {
-                if let Some(last_spot) = last_spot.last() {
-                    position.update_price(last_spot.close);
+        let quotes = source.get_quote_prices(ticker).await?;
+        let mut closes = quotes.closes;
+
+        // dividends and price are both in the quote currency
+        if fetch_dividends && position.dividend_yield.is_none() {
+            if let (Some(price), Ok(dividend)) =
+                (closes.last(), source.get_annual_dividend(ticker).await)
+            {
+                if *price > 0.0 {
+                    position.dividend_yield = Some(dividend / price * 100.0);
+                }
+            }
+        }
+
+        if let Some(quote_currency) = quotes.currency {
+            let (major, factor) = major_currency(&quote_currency);
+            closes.iter_mut().for_each(|close| *close *= factor);
+            match get_fx_rate(major, currency, None, source).await {
+                Ok(rate) => closes.iter_mut().for_each(|close| *close *= rate),
+                Err(e) => {
+                    info(format!(
+                        "Could not convert {} from {} to {}, using the unconverted price: {}",
+                        ticker, major, currency, e
+                    ));
+                    position.unconverted_currency = Some(major.to_string());
                 }
             }
+            position.quote_currency = Some(quote_currency);
+        }
+
+        // if the market is closed, this is the last available price
+        if let Some(last_spot) = closes.last() {
+            position.update_price(*last_spot);
+        }
+
+        // the close before the last one is the previous trading day's close
+        if closes.len() >= 2 {
+            position.previous_close = Some(closes[closes.len() - 2]);
         }
 
         // if no name was provided in the JSON, try to get it from Yahoo Finance
         if position.name.is_none() {
             if let Some(ticker) = &position.ticker {
-                let name = get_quote_name(ticker).await?;
+                let name = source.get_quote_name(ticker).await?;
                 position.name = Some(name);
             }
         }
+    } else if let Some(cash_currency) = &position.currency {
+        match get_fx_rate(cash_currency, currency, None, source).await {
+            Ok(rate) => position.fx_rate = Some(rate),
+            Err(e) => {
+                info(format!(
+                    "Could not convert {} from {} to {}, using the unconverted amount: {}",
+                    position.get_name(),
+                    cash_currency,
+                    currency,
+                    e
+                ));
+                position.unconverted_currency = Some(cash_currency.to_string());
+            }
+        }
     }
 
     Ok(PortfolioPosition {
@@ -117,38 +786,48 @@ pub async fn handle_position(
         ticker: position.ticker.to_owned(),
         asset_class: position.asset_class.to_string(),
         amount: position.amount,
+        target_weight: position.target_weight,
+        currency: position.currency.clone(),
+        dividend_yield: position.dividend_yield,
         last_spot: position.last_spot,
+        previous_close: position.previous_close,
+        unconverted_currency: position.unconverted_currency.clone(),
+        quote_currency: position.quote_currency.clone(),
+        fx_rate: position.fx_rate,
     })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
     use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     #[tokio::test]
+    #[ignore = "needs network access to Yahoo Finance"]
     async fn test_get_quote_name() {
-        let name = get_quote_name("AAPL").await.unwrap();
+        let name = YahooSource.get_quote_name("AAPL").await.unwrap();
         assert_eq!(name, "Apple Inc.");
 
-        let name = get_quote_name("BTC-EUR").await.unwrap();
+        let name = YahooSource.get_quote_name("BTC-EUR").await.unwrap();
         assert_eq!(name, "Bitcoin EUR");
     }
 
     #[tokio::test]
+    #[ignore = "needs network access to Yahoo Finance"]
     async fn test_get_quote_price() {
-        let quote = get_quote_price("AAPL").await.unwrap();
-        assert!(quote.last_quote().unwrap().close > 0.0);
+        let quotes = YahooSource.get_quote_prices("AAPL").await.unwrap();
+        assert!(*quotes.closes.last().unwrap() > 0.0);
+        assert_eq!(quotes.currency.as_deref(), Some("USD"));
     }
 
     #[tokio::test]
+    #[ignore = "needs network access to Yahoo Finance"]
     async fn test_get_historic_price() {
         let date = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
-        let quote = get_historic_price("AAPL", date).await.unwrap();
-        assert_eq!(
-            quote.quotes().unwrap().last().unwrap().close,
-            74.35749816894531
-        );
+        let price = YahooSource.get_historic_price("AAPL", date).await.unwrap();
+        assert_eq!(price, 74.35749816894531);
     }
 
     #[tokio::test]
@@ -158,10 +837,24 @@ mod tests {
             ticker: Some("AAPL".to_string()),
             asset_class: "Stock".to_string(),
             amount: 1.0,
+            target_weight: None,
+            currency: None,
+            dividend_yield: None,
             last_spot: 0.0,
+            previous_close: None,
+            unconverted_currency: None,
+            quote_currency: None,
+            fx_rate: None,
+        };
+
+        let source = MockSource {
+            prices: HashMap::from([("AAPL".to_string(), vec![190.0])]),
+            currencies: HashMap::from([("AAPL".to_string(), "USD".to_string())]),
+            names: HashMap::from([("AAPL".to_string(), "Apple Inc.".to_string())]),
+            ..Default::default()
         };
 
-        let updated_position = handle_position(&mut position)
+        let updated_position = handle_position(&mut position, &source, "USD", false)
             .await
             .expect("Error handling position");
         assert_eq!(updated_position.get_name(), "Apple Inc.");
@@ -169,12 +862,440 @@ mod tests {
             updated_position.get_balance(),
             updated_position.get_amount() * updated_position.last_spot
         );
+        assert_eq!(updated_position.get_balance(), 190.0);
+    }
+
+    #[tokio::test]
+    async fn test_handle_position_with_mock_source() {
+        let source = MockSource {
+            prices: HashMap::from([("AAPL".to_string(), vec![180.0, 200.0])]),
+            names: HashMap::from([("AAPL".to_string(), "Apple Inc.".to_string())]),
+            ..Default::default()
+        };
+        let mut position = PortfolioPosition {
+            ticker: Some("AAPL".to_string()),
+            amount: 2.0,
+            ..Default::default()
+        };
+
+        let updated_position = handle_position(&mut position, &source, "EUR", false)
+            .await
+            .unwrap();
+        assert_eq!(updated_position.get_name(), "Apple Inc.");
+        assert_eq!(updated_position.get_balance(), 400.0);
+        assert_eq!(updated_position.previous_close, Some(180.0));
+
+        let mut unknown = PortfolioPosition {
+            ticker: Some("FOO".to_string()),
+            ..Default::default()
+        };
+        assert!(handle_position(&mut unknown, &source, "EUR", false)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_handle_position_converts_currency() {
+        let source = MockSource {
+            prices: HashMap::from([
+                ("MSFT".to_string(), vec![380.0, 400.0]),
+                ("VOD.L".to_string(), vec![7000.0]),
+                ("USDCHF=X".to_string(), vec![0.5]),
+            ]),
+            currencies: HashMap::from([
+                ("MSFT".to_string(), "USD".to_string()),
+                ("VOD.L".to_string(), "GBp".to_string()),
+            ]),
+            ..Default::default()
+        };
+
+        let mut position = PortfolioPosition {
+            ticker: Some("MSFT".to_string()),
+            name: Some("Microsoft".to_string()),
+            amount: 1.0,
+            ..Default::default()
+        };
+        let converted = handle_position(&mut position, &source, "CHF", false)
+            .await
+            .unwrap();
+        assert_eq!(converted.get_market_price(), Some(200.0));
+        assert_eq!(converted.previous_close, Some(190.0));
+        assert_eq!(converted.get_unconverted_currency(), None);
+
+        // no GBPCHF=X rate, the price stays in pounds
+        let mut position = PortfolioPosition {
+            ticker: Some("VOD.L".to_string()),
+            name: Some("Vodafone".to_string()),
+            amount: 1.0,
+            ..Default::default()
+        };
+        let unconverted = handle_position(&mut position, &source, "CHF", false)
+            .await
+            .unwrap();
+        assert_eq!(unconverted.get_market_price(), Some(70.0));
+        assert_eq!(unconverted.get_unconverted_currency(), Some("GBP"));
+    }
+
+    #[tokio::test]
+    async fn test_handle_position_converts_cash() {
+        let source = MockSource {
+            prices: HashMap::from([("USDEUR=X".to_string(), vec![0.5])]),
+            ..Default::default()
+        };
+
+        let mut position = PortfolioPosition {
+            name: Some("Dollar account".to_string()),
+            asset_class: "Cash".to_string(),
+            currency: Some("USD".to_string()),
+            amount: 100.0,
+            ..Default::default()
+        };
+        let converted = handle_position(&mut position, &source, "EUR", false)
+            .await
+            .unwrap();
+        assert_eq!(converted.get_amount(), 100.0);
+        assert_eq!(converted.get_balance(), 50.0);
+        assert_eq!(converted.get_unconverted_currency(), None);
+
+        // cash in the configured currency needs no rate
+        let mut position = PortfolioPosition {
+            name: Some("Euro account".to_string()),
+            asset_class: "Cash".to_string(),
+            currency: Some("EUR".to_string()),
+            amount: 100.0,
+            ..Default::default()
+        };
+        let position = handle_position(&mut position, &source, "EUR", false)
+            .await
+            .unwrap();
+        assert_eq!(position.get_balance(), 100.0);
+
+        // no CHFEUR=X rate, the amount is used as it is
+        let mut position = PortfolioPosition {
+            name: Some("Franc account".to_string()),
+            asset_class: "Cash".to_string(),
+            currency: Some("CHF".to_string()),
+            amount: 100.0,
+            ..Default::default()
+        };
+        let unconverted = handle_position(&mut position, &source, "EUR", false)
+            .await
+            .unwrap();
+        assert_eq!(unconverted.get_balance(), 100.0);
+        assert_eq!(unconverted.get_unconverted_currency(), Some("CHF"));
+    }
+
+    #[tokio::test]
+    async fn test_handle_position_dividend_yield() {
+        let source = MockSource {
+            prices: HashMap::from([
+                ("KO".to_string(), vec![50.0]),
+                ("PEP".to_string(), vec![100.0]),
+            ]),
+            dividends: HashMap::from([("KO".to_string(), 2.0), ("PEP".to_string(), 5.0)]),
+            ..Default::default()
+        };
+
+        let mut position = PortfolioPosition {
+            name: Some("Coca-Cola".to_string()),
+            ticker: Some("KO".to_string()),
+            amount: 10.0,
+            ..Default::default()
+        };
+        let position = handle_position(&mut position, &source, "USD", true)
+            .await
+            .unwrap();
+        assert_eq!(position.dividend_yield, Some(4.0));
+        assert_eq!(position.annual_dividend_income(), Some(20.0));
+
+        // without the dividend column nothing is fetched
+        let mut position = PortfolioPosition {
+            ticker: Some("KO".to_string()),
+            name: Some("Coca-Cola".to_string()),
+            amount: 10.0,
+            ..Default::default()
+        };
+        let position = handle_position(&mut position, &source, "USD", false)
+            .await
+            .unwrap();
+        assert_eq!(position.dividend_yield, None);
+
+        // a configured yield is not overwritten
+        let mut position = PortfolioPosition {
+            name: Some("PepsiCo".to_string()),
+            ticker: Some("PEP".to_string()),
+            amount: 1.0,
+            dividend_yield: Some(3.0),
+            ..Default::default()
+        };
+        let position = handle_position(&mut position, &source, "USD", true)
+            .await
+            .unwrap();
+        assert_eq!(position.annual_dividend_income(), Some(3.0));
+
+        let cash = PortfolioPosition {
+            amount: 100.0,
+            dividend_yield: Some(3.0),
+            ..Default::default()
+        };
+        assert_eq!(cash.annual_dividend_income(), None);
+    }
+
+    #[tokio::test]
+    async fn test_with_retries() {
+        let attempts = AtomicUsize::new(0);
+        let flaky = || async {
+            match attempts.fetch_add(1, Ordering::Relaxed) {
+                0 | 1 => Err(yahoo::YahooError::FetchFailed(
+                    "503 Service Unavailable".to_string(),
+                )),
+                _ => Ok(42.0),
+            }
+        };
+        let result = with_retries(3, Duration::ZERO, flaky).await;
+        assert_eq!(result.unwrap(), 42.0);
+        assert_eq!(attempts.load(Ordering::Relaxed), 3);
+
+        // an unknown ticker is not retried
+        attempts.store(0, Ordering::Relaxed);
+        let unknown = || async {
+            attempts.fetch_add(1, Ordering::Relaxed);
+            Err::<f64, _>(yahoo::YahooError::FetchFailed("404 Not Found".to_string()))
+        };
+        assert!(with_retries(3, Duration::ZERO, unknown).await.is_err());
+        assert_eq!(attempts.load(Ordering::Relaxed), 1);
+
+        // the last error is returned once the retries are used up
+        attempts.store(0, Ordering::Relaxed);
+        let down = || async {
+            attempts.fetch_add(1, Ordering::Relaxed);
+            Err::<f64, _>(yahoo::YahooError::FetchFailed(
+                "502 Bad Gateway".to_string(),
+            ))
+        };
+        assert!(with_retries(2, Duration::ZERO, down).await.is_err());
+        assert_eq!(attempts.load(Ordering::Relaxed), 3);
+    }
+
+    #[tokio::test]
+    async fn test_cached_source_dividends() {
+        let path = std::env::temp_dir().join(format!(
+            "portfolio_dividend_cache_{}.json",
+            std::process::id()
+        ));
+        let source = MockSource {
+            dividends: HashMap::from([("KO".to_string(), 2.0)]),
+            ..Default::default()
+        };
+        let cached = CachedSource::load(source, Some(path.clone()), 300);
+        assert_eq!(cached.get_annual_dividend("KO").await.unwrap(), 2.0);
+        cached.save().unwrap();
+
+        // fresh dividends are served without asking the source
+        let fresh = CachedSource::load(MockSource::default(), Some(path.clone()), 300);
+        assert_eq!(fresh.get_annual_dividend("KO").await.unwrap(), 2.0);
+
+        // expired ones are used if the source fails and offline
+        let expired = CachedSource::load(MockSource::default(), Some(path.clone()), 0);
+        assert_eq!(expired.get_annual_dividend("KO").await.unwrap(), 2.0);
+        assert!(expired.get_annual_dividend("PEP").await.is_err());
+        let offline = CachedSource::load(MockSource::default(), Some(path.clone()), 0).offline();
+        assert_eq!(offline.get_annual_dividend("KO").await.unwrap(), 2.0);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cached_source() {
+        let path =
+            std::env::temp_dir().join(format!("portfolio_quote_cache_{}.json", std::process::id()));
+        let source = MockSource {
+            prices: HashMap::from([("AAPL".to_string(), vec![180.0, 200.0])]),
+            currencies: HashMap::from([("AAPL".to_string(), "USD".to_string())]),
+            names: HashMap::from([("AAPL".to_string(), "Apple Inc.".to_string())]),
+            ..Default::default()
+        };
+        let cached = CachedSource::load(source, Some(path.clone()), 300);
+        let quotes = cached.get_quote_prices("AAPL").await.unwrap();
+        assert_eq!(cached.get_quote_name("AAPL").await.unwrap(), "Apple Inc.");
+        cached.save().unwrap();
+
+        // fresh quotes are served without asking the source
+        let offline = CachedSource::load(MockSource::default(), Some(path.clone()), 300);
+        assert_eq!(offline.get_quote_prices("AAPL").await.unwrap(), quotes);
+        assert_eq!(offline.get_quote_name("AAPL").await.unwrap(), "Apple Inc.");
+
+        // expired quotes are still better than none when the source fails
+        let expired = CachedSource::load(MockSource::default(), Some(path.clone()), 0);
+        assert_eq!(expired.get_quote_prices("AAPL").await.unwrap(), quotes);
+        assert!(expired.get_quote_prices("MSFT").await.is_err());
+        assert_eq!(expired.stale_tickers(), HashSet::from(["AAPL".to_string()]));
+
+        // offline, unknown tickers have no price instead of an error
+        let source = MockSource {
+            prices: HashMap::from([("MSFT".to_string(), vec![400.0])]),
+            ..Default::default()
+        };
+        let offline = CachedSource::load(source, Some(path.clone()), 0).offline();
+        assert_eq!(offline.get_quote_prices("AAPL").await.unwrap(), quotes);
+        assert_eq!(
+            offline.get_quote_prices("MSFT").await.unwrap(),
+            Quotes::default()
+        );
+        assert_eq!(offline.get_quote_name("MSFT").await.unwrap(), "MSFT");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_merge_duplicates() {
+        let mut positions = from_string(
+            r#"[
+                {"Name": "Apple", "Ticker": "AAPL", "Amount": 1},
+                {"Name": "Cash", "AssetClass": "Cash", "Amount": 100}
+            ]"#,
+            "Stocks",
+        )
+        .unwrap();
+        positions.extend(
+            from_string(
+                r#"[
+                {"Ticker": "aapl", "Amount": 2.5},
+                {"Name": "Cash", "AssetClass": "Cash", "Amount": 50}
+            ]"#,
+                "Stocks",
+            )
+            .unwrap(),
+        );
+
+        let merged = merge_duplicates(positions);
+        assert_eq!(merged.len(), 3);
+        assert_eq!(merged[0].get_name(), "Apple");
+        assert_eq!(merged[0].get_amount(), 3.5);
+        assert_eq!(merged[2].get_amount(), 50.0);
+    }
+
+    #[test]
+    fn test_validate_positions() {
+        let positions = from_string(
+            r#"[
+                {"Ticker": "AAPL", "Amount": 1},
+                {"Name": "Cash", "AssetClass": "Cash", "Amount": 100},
+                {"AssetClass": "Cash", "Amount": 100},
+                {"Name": "Gold coins", "AssetClass": "Gold", "Amount": 500},
+                {"Name": "Typo", "Ticker": " ", "Amount": 1},
+                {"Name": "Sold", "Ticker": "MSFT", "Amount": 0},
+                {"Name": "Spaces", "Ticker": "VOW3 DE", "Amount": 1, "AssetClass": " "},
+                {"Name": "Margin", "AssetClass": "Cash", "Amount": -100}
+            ]"#,
+            "Stocks",
+        )
+        .unwrap();
+
+        assert_eq!(
+            validate_positions(&positions),
+            vec![
+                ValidationError::Unnamed { index: 2 },
+                ValidationError::UnpricedAsset {
+                    name: "Gold coins".to_string(),
+                    asset_class: "Gold".to_string()
+                },
+                ValidationError::EmptyTicker {
+                    name: "Typo".to_string()
+                },
+                ValidationError::NonPositiveAmount {
+                    name: "Sold".to_string(),
+                    amount: 0.0
+                },
+                ValidationError::MalformedTicker {
+                    name: "Spaces".to_string(),
+                    ticker: "VOW3 DE".to_string()
+                },
+                ValidationError::BlankAssetClass {
+                    name: "Spaces".to_string()
+                },
+            ]
+        );
+        assert_eq!(
+            ValidationError::Unnamed { index: 2 }.to_string(),
+            "Position #3 has neither a Name nor a Ticker"
+        );
+    }
+
+    #[test]
+    fn test_validate_portfolio_json() {
+        let data = std::fs::read_to_string("example_data.json").unwrap();
+        assert!(validate_portfolio_json(&data).is_empty());
+
+        let problems = validate_portfolio_json(r#"[{"Ticker": "^GSPC", "Amount": -1}]"#);
+        assert_eq!(problems, vec!["^GSPC has a non-positive Amount (-1)"]);
+
+        let problems = validate_portfolio_json(r#"[{"Ticker": "AAPL"}]"#);
+        assert!(problems[0].starts_with("Not a valid portfolio: missing field `Amount`"));
+    }
+
+    #[test]
+    fn test_get_display_name() {
+        let position = PortfolioPosition {
+            name: Some("Apple Inc.".to_string()),
+            ticker: Some("AAPL".to_string()),
+            asset_class: "Stock".to_string(),
+            amount: 1.0,
+            ..Default::default()
+        };
+        assert_eq!(position.get_display_name(false), "Apple Inc.");
+        assert_eq!(position.get_display_name(true), "AAPL");
+
+        let cash = PortfolioPosition {
+            name: Some("Cash".to_string()),
+            ticker: None,
+            asset_class: "Cash".to_string(),
+            amount: 100.0,
+            ..Default::default()
+        };
+        assert_eq!(cash.get_display_name(true), "Cash");
+    }
+
+    #[test]
+    fn test_get_daily_variation_percent() {
+        let mut position = PortfolioPosition {
+            ticker: Some("AAPL".to_string()),
+            amount: 1.0,
+            last_spot: 110.0,
+            previous_close: Some(100.0),
+            ..Default::default()
+        };
+        assert_eq!(position.get_daily_variation_percent(), Some(10.0));
+
+        position.previous_close = None;
+        assert_eq!(position.get_daily_variation_percent(), None);
     }
 
     #[tokio::test]
     async fn test_from_file() {
         let positions_str = fs::read_to_string("example_data.json").unwrap();
-        let positions = from_string(&positions_str);
+        let positions = from_string(&positions_str, "Stocks").unwrap();
         assert_eq!(positions.len(), 6);
     }
+
+    #[test]
+    fn test_from_string_default_asset_class() {
+        let positions = from_string(r#"[{"Ticker": "AAPL", "Amount": 1}]"#, "Stocks").unwrap();
+        assert_eq!(positions[0].get_asset_class(), "Stocks");
+
+        let positions = from_string(
+            r#"[{"Ticker": "AAPL", "AssetClass": "Tech", "Amount": 1}]"#,
+            "Stocks",
+        )
+        .unwrap();
+        assert_eq!(positions[0].get_asset_class(), "Tech");
+    }
+
+    #[test]
+    fn test_from_string_invalid_json() {
+        let result = from_string(r#"[{"Ticker": "AAPL", "Amount": }]"#, "Stocks");
+        assert!(result
+            .unwrap_err()
+            .starts_with("JSON was not well-formatted: "));
+    }
 }